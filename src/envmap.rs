@@ -0,0 +1,154 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Environment maps, the light coming from every direction around a scene,
+//! stored as an [equirectangular] (latitude and longitude) image, usually
+//! loaded from a Radiance HDR file.
+//!
+//! [equirectangular]: https://en.wikipedia.org/wiki/Equirectangular_projection
+//!
+
+use std::f64::consts::PI;
+use std::io::Read;
+
+use crate::geometry::Vec3f;
+use crate::hdr::HdrError;
+use crate::image::HdrImage;
+use crate::image::Image;
+use crate::image::Pixel;
+use crate::image::Rgbaf;
+use crate::sampler::Filter;
+use crate::sampler::Sampler;
+use crate::sampler::Wrap;
+
+/// An environment map for image-based lighting.
+/// Y is up, and the middle of the image is straight ahead, towards -Z.
+pub struct EnvMap {
+    image: HdrImage,
+    sampler: Sampler,
+    /// the radiance projected onto the first 9 spherical harmonics, for each channel
+    harmonics: [[f64; 3]; 9],
+}
+
+impl EnvMap {
+    /// An environment map from an equirectangular image, twice as wide as it is high.
+    pub fn new(image: HdrImage) -> EnvMap {
+        let mut sampler = Sampler::new(Filter::Bilinear, Wrap::Repeat);
+        // there is nothing above the top or below the bottom to wrap to
+        sampler.wrap_v = Wrap::ClampToEdge;
+        let harmonics = project_harmonics(&image);
+        EnvMap {
+            image,
+            sampler,
+            harmonics,
+        }
+    }
+
+    /// Read an environment map from Radiance HDR data.
+    pub fn from_hdr<R: Read>(data: &mut R) -> Result<EnvMap, HdrError> {
+        Ok(EnvMap::new(Image::from_hdr(data)?))
+    }
+
+    pub fn image(&self) -> &HdrImage {
+        &self.image
+    }
+
+    /// The light coming from `direction`, which need not be normalized.
+    pub fn radiance(&self, direction: Vec3f) -> Rgbaf {
+        let d = direction.normalized();
+        let u = 0.5 + d.x.atan2(-d.z) / (2.0 * PI);
+        let v = 0.5 + d.y.clamp(-1.0, 1.0).asin() / PI;
+        self.sampler.sample(&self.image, u, v)
+    }
+
+    /// The light a white, perfectly diffuse surface facing `normal` reflects,
+    /// that is the irradiance divided by pi. Smooth, so it is fine to look up per vertex.
+    pub fn irradiance(&self, normal: Vec3f) -> Rgbaf {
+        let basis = harmonics_basis(normal.normalized());
+        // how much each band of the cosine lobe lets through, over pi
+        const BANDS: [f64; 9] = [
+            1.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+        ];
+        let mut color = [0.0, 0.0, 0.0, 1.0];
+        for ((coefficients, y), band) in self.harmonics.iter().zip(basis.iter()).zip(BANDS.iter()) {
+            for (c, l) in color.iter_mut().zip(coefficients.iter()) {
+                *c += band * l * y;
+            }
+        }
+        for c in &mut color[..3] {
+            *c = c.max(0.0);
+        }
+        color
+    }
+}
+
+/// The direction at the middle of texel `x`, `y`, counted from the top.
+fn texel_direction(image: &HdrImage, x: usize, y: usize) -> Vec3f {
+    let longitude = ((x as f64 + 0.5) / image.width as f64 - 0.5) * 2.0 * PI;
+    let latitude = (0.5 - (y as f64 + 0.5) / image.height as f64) * PI;
+    Vec3f::new(
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+        -latitude.cos() * longitude.cos(),
+    )
+}
+
+/// The first 9 real spherical harmonics at the normalized direction `d`.
+fn harmonics_basis(d: Vec3f) -> [f64; 9] {
+    [
+        0.282_095,
+        0.488_603 * d.y,
+        0.488_603 * d.z,
+        0.488_603 * d.x,
+        1.092_548 * d.x * d.y,
+        1.092_548 * d.y * d.z,
+        0.315_392 * (3.0 * d.z * d.z - 1.0),
+        1.092_548 * d.x * d.z,
+        0.546_274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+/// Integrate the radiance times each harmonic over the sphere,
+/// see Ramamoorthi and Hanrahan, "An Efficient Representation for Irradiance
+/// Environment Maps".
+fn project_harmonics(image: &HdrImage) -> [[f64; 3]; 9] {
+    let mut harmonics = [[0.0; 3]; 9];
+    // texels near the poles cover less of the sphere
+    let texel_angle = (2.0 * PI / image.width as f64) * (PI / image.height as f64);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let direction = texel_direction(image, x, y);
+            let solid_angle = texel_angle * (1.0 - direction.y * direction.y).sqrt();
+            let radiance = image
+                .color_space
+                .to_linear(image.pixels[x + y * image.width].to_rgbaf());
+            let basis = harmonics_basis(direction);
+            for (coefficients, y) in harmonics.iter_mut().zip(basis.iter()) {
+                for (l, r) in coefficients.iter_mut().zip(radiance.iter()) {
+                    *l += r * y * solid_angle;
+                }
+            }
+        }
+    }
+    harmonics
+}
@@ -189,6 +189,73 @@ impl<T: Copy + ops::Mul<T, Output = T> + ops::Sub<T, Output = T>> Vec3<T> {
     }
 }
 
+/// An axis aligned bounding box.
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3f,
+    pub max: Vec3f,
+}
+
+impl Aabb {
+    /// The smallest box holding all the points, or `None` if there are none.
+    pub fn from_points<I: IntoIterator<Item = Vec3f>>(points: I) -> Option<Aabb> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(
+            Aabb {
+                min: first,
+                max: first,
+            },
+            |aabb, p| aabb.union(&Aabb { min: p, max: p }),
+        ))
+    }
+
+    /// The smallest box holding both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut union = *self;
+        for i in 0..3 {
+            union.min[i] = union.min[i].min(other.min[i]);
+            union.max[i] = union.max[i].max(other.max[i]);
+        }
+        union
+    }
+
+    pub fn center(&self) -> Vec3f {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vec3f {
+        self.max - self.min
+    }
+
+    /// The transform that moves the box's center to the origin and scales it
+    /// evenly to fit [-1, 1] in every axis, touching the sides in its longest one.
+    pub fn to_unit(&self) -> Matrix {
+        let size = self.size();
+        self.centered_and_scaled_to(size.x.max(size.y).max(size.z) / 2.0)
+    }
+
+    /// The transform that moves the box's center to the origin and scales it
+    /// evenly to fit in the unit sphere, with its corners on the sphere, so
+    /// that it fits the view of a camera looking at the sphere from any side.
+    pub fn to_unit_sphere(&self) -> Matrix {
+        self.centered_and_scaled_to(self.size().norm() / 2.0)
+    }
+
+    /// moves the center to the origin and scales `extent` to 1
+    fn centered_and_scaled_to(&self, extent: f64) -> Matrix {
+        // a box around a single point only needs moving
+        let scale = if extent > 0.0 { 1.0 / extent } else { 1.0 };
+        let center = self.center();
+        let mut m = Matrix::identity(4);
+        for i in 0..3 {
+            m.put(i, i, scale);
+            m.put(i, 3, -center[i] * scale);
+        }
+        m
+    }
+}
+
 pub struct Matrix {
     m: Vec<Vec<f64>>,
     pub rows: usize,
@@ -262,3 +329,52 @@ impl ops::Mul<&Matrix> for Matrix {
         &self * other
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(m: &Matrix, v: Vec3f) -> Vec3f {
+        Vec3f::from_m(&(m * &Matrix::from_v(v)))
+    }
+
+    fn assert_close(a: Vec3f, b: Vec3f) {
+        assert!((a - b).norm() < 1e-9, "{} is not {}", a, b);
+    }
+
+    // flat in z, so the scale must not come from the smallest size
+    fn flat_box() -> Aabb {
+        Aabb::from_points(vec![Vec3f::new(1.0, 2.0, 3.0), Vec3f::new(5.0, 4.0, 3.0)]).unwrap()
+    }
+
+    #[test]
+    fn fits_boxes_in_the_unit_cube() {
+        let b = flat_box();
+        let m = b.to_unit();
+        assert_close(apply(&m, b.center()), Vec3f::zero());
+        assert_close(apply(&m, b.min), Vec3f::new(-1.0, -0.5, 0.0));
+        assert_close(apply(&m, b.max), Vec3f::new(1.0, 0.5, 0.0));
+    }
+
+    #[test]
+    fn fits_boxes_in_the_unit_sphere() {
+        let b = flat_box();
+        let m = b.to_unit_sphere();
+        assert_close(apply(&m, b.center()), Vec3f::zero());
+        let corner = apply(&m, b.max);
+        assert!((corner.norm() - 1.0).abs() < 1e-9);
+        assert_close(apply(&m, b.min), corner * -1.0);
+    }
+
+    #[test]
+    fn only_moves_single_points() {
+        let point = Aabb::from_points(vec![Vec3f::new(1.0, 2.0, 3.0)]).unwrap();
+        for m in &[point.to_unit(), point.to_unit_sphere()] {
+            assert_close(apply(m, point.min), Vec3f::zero());
+            assert_close(
+                apply(m, Vec3f::new(2.0, 2.0, 3.0)),
+                Vec3f::new(1.0, 0.0, 0.0),
+            );
+        }
+    }
+}
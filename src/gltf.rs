@@ -0,0 +1,819 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Reading [glTF] 2.0 scenes, as `.gltf` JSON with buffers and images in
+//! other files or in data URIs, or as binary `.glb` files.
+//!
+//! [glTF]: https://www.khronos.org/gltf/
+//!
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::geometry::Matrix;
+use crate::geometry::Vec3;
+use crate::geometry::Vec3f;
+use crate::image::RgbaImage;
+use crate::json::Json;
+use crate::json::JsonError;
+use crate::model::Face;
+use crate::model::Model;
+use crate::model::NormalMode;
+use crate::mtl::Material;
+
+/// The meshes a glTF scene places, with their base color textures.
+pub struct GltfScene {
+    /// a model for every primitive of every mesh the scene's nodes place,
+    /// in the scene's coordinates, with the node transforms applied
+    pub models: Vec<GltfModel>,
+    /// the base color textures, in sRGB, only PNG images are read
+    pub textures: Vec<RgbaImage>,
+}
+
+/// A primitive of a mesh placed in a glTF scene.
+pub struct GltfModel {
+    /// the mesh's name, or the node's if the mesh has none
+    pub name: String,
+    /// if the primitive has a material, every face has a material with the
+    /// base color factor as its diffuse color and opacity
+    pub model: Model,
+    /// the base color texture, an index into the scene's `textures`
+    pub texture: Option<usize>,
+}
+
+impl GltfScene {
+    /// Read the .gltf or .glb file at `path`,
+    /// with the files it refers to looked for next to it.
+    pub fn from_gltf_file<P: AsRef<Path>>(path: P) -> Result<GltfScene, GltfError> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let data = fs::read(path).map_err(GltfError::Io)?;
+        GltfScene::from_gltf_with(&data, |uri| fs::read(dir.join(uri)))
+    }
+
+    /// Parse the given .gltf or .glb data, reading the buffers and images
+    /// it refers to by URI with `load_uri`, which gets the URI decoded
+    /// into a relative path. Data URIs are decoded without it.
+    ///
+    /// Triangles, triangle strips, and triangle fans are read, with
+    /// `POSITION`, `NORMAL`, and the `TEXCOORD_n` the base color texture uses,
+    /// points and lines are skipped. Primitives without normals get flat ones,
+    /// like the glTF spec says. Sparse accessors, and accessors without data,
+    /// are not supported.
+    pub fn from_gltf_with<F>(data: &[u8], load_uri: F) -> Result<GltfScene, GltfError>
+    where
+        F: FnMut(&str) -> io::Result<Vec<u8>>,
+    {
+        let (json, bin) = if data.starts_with(b"glTF") {
+            split_glb(data)?
+        } else {
+            (data, None)
+        };
+        let text = std::str::from_utf8(json)
+            .map_err(|_| GltfError::Invalid("JSON is not UTF-8".to_owned()))?;
+        let root = Json::parse(text).map_err(GltfError::Json)?;
+
+        let version = root
+            .get("asset")
+            .and_then(|a| a.get("version"))
+            .and_then(Json::as_str);
+        match version {
+            Some(v) if v.starts_with("2.") => {}
+            other => {
+                return Err(GltfError::UnsupportedVersion(
+                    other.unwrap_or("none").to_owned(),
+                ))
+            }
+        }
+        if let Some(required) = root.get("extensionsRequired").and_then(Json::as_array) {
+            if let Some(extension) = required.first() {
+                let name = extension.as_str().unwrap_or("?");
+                return Err(GltfError::Unsupported(format!(
+                    "required extension {}",
+                    name
+                )));
+            }
+        }
+
+        let mut reader = Reader {
+            root: &root,
+            buffers: Vec::new(),
+            load_uri,
+            scene: GltfScene {
+                models: Vec::new(),
+                textures: Vec::new(),
+            },
+            image_textures: Vec::new(),
+        };
+        reader.load_buffers(bin)?;
+        reader.image_textures = vec![None; array(&root, "images").len()];
+
+        let nodes = array(&root, "nodes");
+        let scene_index = root.get("scene").and_then(Json::as_usize).unwrap_or(0);
+        let roots: Vec<usize> = match array(&root, "scenes").get(scene_index) {
+            Some(scene) => indices(scene.get("nodes")),
+            // without scenes, draw the nodes that are not children of others
+            None => {
+                let mut is_child = vec![false; nodes.len()];
+                for child in nodes.iter().flat_map(|n| indices(n.get("children"))) {
+                    if let Some(is_child) = is_child.get_mut(child) {
+                        *is_child = true;
+                    }
+                }
+                (0..nodes.len()).filter(|&i| !is_child[i]).collect()
+            }
+        };
+        reader.visit(roots)?;
+        Ok(reader.scene)
+    }
+}
+
+/// Why glTF data could not be read.
+#[derive(Debug)]
+pub enum GltfError {
+    /// the data, or a file it refers to, could not be read
+    Io(io::Error),
+    /// the JSON is malformed
+    Json(JsonError),
+    /// the .glb container is malformed
+    BadGlb,
+    /// the asset is not glTF 2.0
+    UnsupportedVersion(String),
+    /// the data uses something we don't read, like sparse accessors
+    Unsupported(String),
+    /// something is missing or malformed, what and where
+    Invalid(String),
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GltfError::Io(e) => write!(f, "cannot read glTF data: {}", e),
+            GltfError::Json(e) => write!(f, "bad glTF JSON: {}", e),
+            GltfError::BadGlb => write!(f, "malformed binary glTF"),
+            GltfError::UnsupportedVersion(v) => write!(f, "unsupported glTF version {:?}", v),
+            GltfError::Unsupported(what) => write!(f, "unsupported glTF feature: {}", what),
+            GltfError::Invalid(what) => write!(f, "invalid glTF: {}", what),
+        }
+    }
+}
+
+impl error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            GltfError::Io(e) => Some(e),
+            GltfError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Split a .glb file into its JSON chunk and its binary chunk, if it has one.
+fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>), GltfError> {
+    let u32_at = |pos: usize| {
+        data.get(pos..pos + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or(GltfError::BadGlb)
+    };
+    if u32_at(4)? != 2 {
+        return Err(GltfError::UnsupportedVersion(format!("glb {}", u32_at(4)?)));
+    }
+    let length = u32_at(8)?.min(data.len());
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= length {
+        let (chunk_length, kind) = (u32_at(pos)?, u32_at(pos + 4)?);
+        let chunk = data
+            .get(pos + 8..pos + 8 + chunk_length)
+            .ok_or(GltfError::BadGlb)?;
+        chunks.push((kind, chunk));
+        pos += 8 + chunk_length;
+    }
+    const JSON: usize = 0x4e4f_534a;
+    const BIN: usize = 0x004e_4942;
+    match chunks.as_slice() {
+        [(JSON, json)] => Ok((json, None)),
+        [(JSON, json), (BIN, bin), ..] => Ok((json, Some(bin))),
+        _ => Err(GltfError::BadGlb),
+    }
+}
+
+/// The elements of a top level array, or none if it is missing.
+fn array<'a>(root: &'a Json, name: &str) -> &'a [Json] {
+    root.get(name).and_then(Json::as_array).unwrap_or(&[])
+}
+
+/// An array of indices, or none if it is missing.
+fn indices(value: Option<&Json>) -> Vec<usize> {
+    value
+        .and_then(Json::as_array)
+        .map(|a| a.iter().filter_map(Json::as_usize).collect())
+        .unwrap_or_default()
+}
+
+struct Reader<'a, F> {
+    root: &'a Json,
+    buffers: Vec<Vec<u8>>,
+    load_uri: F,
+    scene: GltfScene,
+    /// for each image, its index in the scene's textures,
+    /// once it has been read, if it could be
+    image_textures: Vec<Option<Option<usize>>>,
+}
+
+impl<'a, F: FnMut(&str) -> io::Result<Vec<u8>>> Reader<'a, F> {
+    /// the `i`th element of the top level array `name`
+    fn item(&self, name: &str, i: usize) -> Result<&'a Json, GltfError> {
+        array(self.root, name)
+            .get(i)
+            .ok_or_else(|| GltfError::Invalid(format!("{}[{}] is missing", name, i)))
+    }
+
+    /// the data a URI refers to, decoded if it is a data URI
+    fn load(&mut self, uri: &str) -> Result<Vec<u8>, GltfError> {
+        if let Some(data) = uri.strip_prefix("data:") {
+            let (header, payload) = data
+                .split_once(',')
+                .ok_or_else(|| GltfError::Invalid("data URI without data".to_owned()))?;
+            if !header.ends_with(";base64") {
+                return Err(GltfError::Unsupported("data URIs not in base64".to_owned()));
+            }
+            return base64_decode(payload)
+                .ok_or_else(|| GltfError::Invalid("bad base64 in data URI".to_owned()));
+        }
+        (self.load_uri)(&percent_decode(uri)).map_err(GltfError::Io)
+    }
+
+    fn load_buffers(&mut self, bin: Option<&[u8]>) -> Result<(), GltfError> {
+        for (i, buffer) in array(self.root, "buffers").iter().enumerate() {
+            let data = match (buffer.get("uri").and_then(Json::as_str), bin) {
+                (Some(uri), _) => self.load(uri)?,
+                // the first buffer of a .glb file can be its binary chunk
+                (None, Some(bin)) if i == 0 => bin.to_vec(),
+                (None, _) => return Err(GltfError::Invalid(format!("buffers[{}] has no data", i))),
+            };
+            let length = buffer
+                .get("byteLength")
+                .and_then(Json::as_usize)
+                .unwrap_or(0);
+            if data.len() < length {
+                return Err(GltfError::Invalid(format!("buffers[{}] is too short", i)));
+            }
+            self.buffers.push(data);
+        }
+        Ok(())
+    }
+
+    /// Read an accessor, as `components` floats per element, checking that the
+    /// accessor has that many, and converting normalized integers to floats.
+    fn accessor(&self, index: usize, components: usize) -> Result<Vec<f64>, GltfError> {
+        let invalid = |what: &str| GltfError::Invalid(format!("accessors[{}] {}", index, what));
+        let accessor = self.item("accessors", index)?;
+        if accessor.get("sparse").is_some() {
+            return Err(GltfError::Unsupported("sparse accessors".to_owned()));
+        }
+        let count = accessor
+            .get("count")
+            .and_then(Json::as_usize)
+            .ok_or_else(|| invalid("has no count"))?;
+        let kind = accessor.get("type").and_then(Json::as_str).unwrap_or("");
+        let found = match kind {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            _ => 0,
+        };
+        if found != components {
+            return Err(invalid(&format!(
+                "is {:?}, not {} components",
+                kind, components
+            )));
+        }
+        let component_type = accessor
+            .get("componentType")
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return Err(invalid("has an unknown component type")),
+        };
+        // accessors without data are all zeros, which only makes sense with sparse data
+        let view_index = accessor
+            .get("bufferView")
+            .and_then(Json::as_usize)
+            .ok_or_else(|| GltfError::Unsupported("accessors without buffer views".to_owned()))?;
+
+        let view = self.item("bufferViews", view_index)?;
+        let buffer_index = view.get("buffer").and_then(Json::as_usize).unwrap_or(0);
+        let buffer = self
+            .buffers
+            .get(buffer_index)
+            .ok_or_else(|| invalid("refers to a missing buffer"))?;
+        let usize_of =
+            |json: &Json, name: &str| json.get(name).and_then(Json::as_usize).unwrap_or(0);
+        let element_size = size * components;
+        let stride = match usize_of(view, "byteStride") {
+            0 => element_size,
+            stride => stride,
+        };
+        // the sizes come from the file, so they may be anything, even too big to add up
+        let start = usize_of(view, "byteOffset").checked_add(usize_of(accessor, "byteOffset"));
+        let view_end = usize_of(view, "byteOffset").checked_add(usize_of(view, "byteLength"));
+        let end = match count.checked_sub(1) {
+            Some(last) => stride
+                .checked_mul(last)
+                .and_then(|offset| offset.checked_add(element_size))
+                .and_then(|length| start?.checked_add(length)),
+            None => start,
+        };
+        let start = match (start, end, view_end) {
+            (Some(start), Some(end), Some(view_end)) if end <= view_end.min(buffer.len()) => start,
+            _ => return Err(invalid("reads past the end of its buffer view")),
+        };
+
+        let normalized = accessor
+            .get("normalized")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let at = start + element * stride + component * size;
+                let b = &buffer[at..at + size];
+                let value = match component_type {
+                    5120 => b[0] as i8 as f64,
+                    5121 => b[0] as f64,
+                    5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                };
+                values.push(match (normalized, component_type) {
+                    (true, 5120) => (value / 127.0).max(-1.0),
+                    (true, 5121) => value / 255.0,
+                    (true, 5122) => (value / 32767.0).max(-1.0),
+                    (true, 5123) => value / 65535.0,
+                    _ => value,
+                });
+            }
+        }
+        Ok(values)
+    }
+
+    /// Add the models of the nodes and their children, depth first.
+    fn visit(&mut self, roots: Vec<usize>) -> Result<(), GltfError> {
+        let identity = Matrix::identity(4);
+        // the transform of each node to the scene, once it is visited
+        let mut transforms: Vec<Option<Matrix>> = Vec::new();
+        transforms.resize_with(array(self.root, "nodes").len(), || None);
+        // the nodes left to visit, with their parents, the next one last
+        let mut stack: Vec<(usize, Option<usize>)> =
+            roots.into_iter().rev().map(|i| (i, None)).collect();
+        while let Some((index, parent)) = stack.pop() {
+            let node = self.item("nodes", index)?;
+            // nodes have one parent at most, so nodes seen twice are shared, or in a cycle
+            if transforms[index].is_some() {
+                return Err(GltfError::Invalid(format!(
+                    "nodes[{}] is used more than once",
+                    index
+                )));
+            }
+            let parent_transform = parent
+                .and_then(|p| transforms[p].as_ref())
+                .unwrap_or(&identity);
+            let transform = parent_transform * &node_transform(node);
+            if let Some(mesh_index) = node.get("mesh").and_then(Json::as_usize) {
+                let mesh = self.item("meshes", mesh_index)?;
+                let name = mesh
+                    .get("name")
+                    .or_else(|| node.get("name"))
+                    .and_then(Json::as_str)
+                    .map_or_else(|| format!("mesh {}", mesh_index), str::to_owned);
+                for primitive in mesh
+                    .get("primitives")
+                    .and_then(Json::as_array)
+                    .unwrap_or(&[])
+                {
+                    if let Some(model) = self.primitive(&name, primitive, &transform)? {
+                        self.scene.models.push(model);
+                    }
+                }
+            }
+            transforms[index] = Some(transform);
+            for child in indices(node.get("children")).into_iter().rev() {
+                stack.push((child, Some(index)));
+            }
+        }
+        Ok(())
+    }
+
+    fn primitive(
+        &mut self,
+        name: &str,
+        primitive: &Json,
+        transform: &Matrix,
+    ) -> Result<Option<GltfModel>, GltfError> {
+        let mode = primitive.get("mode").and_then(Json::as_usize).unwrap_or(4);
+        if !(4..=6).contains(&mode) {
+            return Ok(None);
+        }
+        let attributes = primitive.get("attributes");
+        let attribute = |name: &str| {
+            attributes
+                .and_then(|a| a.get(name))
+                .and_then(Json::as_usize)
+        };
+
+        let material = match primitive.get("material").and_then(Json::as_usize) {
+            Some(i) => Some(self.item("materials", i)?),
+            None => None,
+        };
+        let pbr = material.and_then(|m| m.get("pbrMetallicRoughness"));
+        let base_texture = pbr.and_then(|p| p.get("baseColorTexture"));
+        let texture = match base_texture
+            .and_then(|t| t.get("index"))
+            .and_then(Json::as_usize)
+        {
+            Some(i) => self.texture(i)?,
+            None => None,
+        };
+        let tex_coord = base_texture
+            .and_then(|t| t.get("texCoord"))
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+
+        let position = attribute("POSITION")
+            .ok_or_else(|| GltfError::Invalid(format!("{} has no POSITION", name)))?;
+        let verts: Vec<Vec3f> = self
+            .accessor(position, 3)?
+            .chunks(3)
+            .map(|p| Vec3f::new(p[0], p[1], p[2]))
+            .collect();
+        let norms: Vec<Vec3f> = match attribute("NORMAL") {
+            Some(normal) => self
+                .accessor(normal, 3)?
+                .chunks(3)
+                .map(|n| Vec3f::new(n[0], n[1], n[2]))
+                .collect(),
+            None => Vec::new(),
+        };
+        let uvs: Vec<Vec3f> = match attribute(&format!("TEXCOORD_{}", tex_coord)) {
+            // glTF's v goes down from the top of the texture, ours up from the bottom
+            Some(uv) => self
+                .accessor(uv, 2)?
+                .chunks(2)
+                .map(|t| Vec3f::new(t[0], 1.0 - t[1], 0.0))
+                .collect(),
+            None => Vec::new(),
+        };
+        for (attribute, len) in [("NORMAL", norms.len()), ("TEXCOORD", uvs.len())].iter() {
+            if *len != 0 && *len != verts.len() {
+                return Err(GltfError::Invalid(format!(
+                    "{} has the wrong number of {}",
+                    name, attribute
+                )));
+            }
+        }
+
+        let order: Vec<usize> = match primitive.get("indices").and_then(Json::as_usize) {
+            Some(i) => self.accessor(i, 1)?.iter().map(|&i| i as usize).collect(),
+            None => (0..verts.len()).collect(),
+        };
+        if let Some(&i) = order.iter().find(|&&i| i >= verts.len()) {
+            return Err(GltfError::Invalid(format!(
+                "{} has vertex index {} out of range",
+                name, i
+            )));
+        }
+        let faces = triangles(mode, &order)
+            .into_iter()
+            .map(|[a, b, c]| {
+                let verts = Vec3::new(a, b, c);
+                Face {
+                    verts,
+                    uvs: Some(verts).filter(|_| !uvs.is_empty()),
+                    norms: Some(verts).filter(|_| !norms.is_empty()),
+                    material: material.map(|_| 0),
+                    smoothing_group: 0,
+                }
+            })
+            .collect();
+
+        let mut model = Model::from_faces(verts, uvs, norms, faces, NormalMode::Flat);
+        model.transform(transform);
+        if let Some(material) = material {
+            let mut base =
+                Material::new(material.get("name").and_then(Json::as_str).unwrap_or(name));
+            if let Some(factor) = pbr.and_then(|p| p.get("baseColorFactor")) {
+                let channel = |i: usize| factor.at(i).and_then(Json::as_f64).unwrap_or(1.0);
+                base.diffuse = [channel(0), channel(1), channel(2)];
+                base.dissolve = channel(3);
+            }
+            model.set_materials(vec![base]);
+        }
+        Ok(Some(GltfModel {
+            name: name.to_owned(),
+            model,
+            texture,
+        }))
+    }
+
+    /// The scene texture for the glTF texture, reading its image the first
+    /// time it is used, or `None` if it is not a PNG image.
+    fn texture(&mut self, index: usize) -> Result<Option<usize>, GltfError> {
+        let source = self
+            .item("textures", index)?
+            .get("source")
+            .and_then(Json::as_usize);
+        let image_index = match source {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        if let Some(Some(texture)) = self.image_textures.get(image_index) {
+            return Ok(*texture);
+        }
+        let image = self.item("images", image_index)?;
+        let data = match (
+            image.get("uri").and_then(Json::as_str),
+            image.get("bufferView").and_then(Json::as_usize),
+        ) {
+            (Some(uri), _) => self.load(uri)?,
+            (None, Some(view_index)) => {
+                let view = self.item("bufferViews", view_index)?;
+                let usize_of = |name: &str| view.get(name).and_then(Json::as_usize).unwrap_or(0);
+                let (start, length) = (usize_of("byteOffset"), usize_of("byteLength"));
+                // like accessors, too big to add up is out of the buffer too
+                self.buffers
+                    .get(usize_of("buffer"))
+                    .and_then(|buffer| buffer.get(start..start.checked_add(length)?))
+                    .ok_or_else(|| {
+                        GltfError::Invalid(format!("images[{}] is out of its buffer", image_index))
+                    })?
+                    .to_vec()
+            }
+            (None, None) => {
+                return Err(GltfError::Invalid(format!(
+                    "images[{}] has no data",
+                    image_index
+                )))
+            }
+        };
+        let texture = if data.starts_with(b"\x89PNG") {
+            let image = RgbaImage::from_png(&mut data.as_slice())
+                .map_err(|e| GltfError::Invalid(format!("images[{}]: {}", image_index, e)))?;
+            self.scene.textures.push(image);
+            Some(self.scene.textures.len() - 1)
+        } else {
+            None
+        };
+        self.image_textures[image_index] = Some(texture);
+        Ok(texture)
+    }
+}
+
+/// The triangles of a primitive, as indices into its vertices.
+fn triangles(mode: usize, order: &[usize]) -> Vec<[usize; 3]> {
+    match mode {
+        // strips alternate their winding, flip every other triangle back
+        5 => (2..order.len())
+            .map(|i| {
+                if i % 2 == 0 {
+                    [order[i - 2], order[i - 1], order[i]]
+                } else {
+                    [order[i - 1], order[i - 2], order[i]]
+                }
+            })
+            .collect(),
+        6 => (2..order.len())
+            .map(|i| [order[0], order[i - 1], order[i]])
+            .collect(),
+        _ => order.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect(),
+    }
+}
+
+/// The node's `matrix`, or its `translation`, `rotation`, and `scale`, applied in reverse order.
+fn node_transform(node: &Json) -> Matrix {
+    let numbers = |name: &str, default: &[f64]| -> Vec<f64> {
+        match node.get(name).and_then(Json::as_array) {
+            Some(a) if a.len() == default.len() => {
+                a.iter().map(|n| n.as_f64().unwrap_or(0.0)).collect()
+            }
+            _ => default.to_vec(),
+        }
+    };
+    let mut m = Matrix::identity(4);
+    if node.get("matrix").is_some() {
+        let identity = [
+            1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+        ];
+        // glTF stores matrices column major
+        for (i, value) in numbers("matrix", &identity).iter().enumerate() {
+            m.put(i % 4, i / 4, *value);
+        }
+        return m;
+    }
+    let t = numbers("translation", &[0.0, 0.0, 0.0]);
+    let q = numbers("rotation", &[0.0, 0.0, 0.0, 1.0]);
+    let s = numbers("scale", &[1.0, 1.0, 1.0]);
+    let (x, y, z, w) = (q[0], q[1], q[2], q[3]);
+    let rotation = [
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - z * w),
+            2.0 * (x * z + y * w),
+        ],
+        [
+            2.0 * (x * y + z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - x * w),
+        ],
+        [
+            2.0 * (x * z - y * w),
+            2.0 * (y * z + x * w),
+            1.0 - 2.0 * (x * x + y * y),
+        ],
+    ];
+    for r in 0..3 {
+        for c in 0..3 {
+            m.put(r, c, rotation[r][c] * s[c]);
+        }
+        m.put(r, 3, t[r]);
+    }
+    m
+}
+
+/// Decode standard base64, ignoring padding, or `None` if it is not base64.
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut bits, mut nbits) = (0u32, 0);
+    for c in text
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | value as u32;
+        nbits += 6;
+        if nbits >= 8 {
+            nbits -= 8;
+            bytes.push((bits >> nbits) as u8);
+            bits &= (1 << nbits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Decode the `%XX` escapes of a URI, like `%20` for spaces in file names.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a scene with the given nodes, whose meshes are one triangle,
+    /// read with the given accessor
+    fn scene(nodes: &str, accessor: &str) -> Result<GltfScene, GltfError> {
+        let json = format!(
+            r#"{{"asset": {{"version": "2.0"}}, "nodes": [{}],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}],
+                "accessors": [{}],
+                "bufferViews": [{{"buffer": 0, "byteLength": 36}}],
+                "buffers": [{{"byteLength": 36, "uri":
+                    "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"}}]}}"#,
+            nodes, accessor
+        );
+        GltfScene::from_gltf_with(json.as_bytes(), |_| Ok(Vec::new()))
+    }
+
+    const TRIANGLE: &str =
+        r#"{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}"#;
+
+    fn invalid(result: Result<GltfScene, GltfError>) -> String {
+        match result {
+            Err(GltfError::Invalid(what)) => what,
+            Err(e) => panic!("expected an invalid glTF error, not {}", e),
+            Ok(_) => panic!("expected an invalid glTF error"),
+        }
+    }
+
+    #[test]
+    fn reads_transformed_triangles() {
+        let scene = scene(r#"{"mesh": 0, "translation": [0, 0, 5]}"#, TRIANGLE).unwrap();
+        assert_eq!(scene.models.len(), 1);
+        let model = &scene.models[0].model;
+        assert_eq!(model.nfaces(), 1);
+        let v = model.fvert(0, 1);
+        assert_eq!((v.x, v.y, v.z), (1.0, 0.0, 5.0));
+    }
+
+    #[test]
+    fn rejects_accessors_past_their_buffer() {
+        let counts = [4, usize::MAX / 12 + 1, usize::MAX / 4, usize::MAX];
+        for count in counts.iter() {
+            let accessor = TRIANGLE.replace(r#""count": 3"#, &format!(r#""count": {}"#, count));
+            let what = invalid(scene(r#"{"mesh": 0}"#, &accessor));
+            assert!(what.contains("past the end"), "{}", what);
+        }
+        let accessor = TRIANGLE.replace("{", &format!(r#"{{"byteOffset": {}, "#, usize::MAX));
+        invalid(scene(r#"{"mesh": 0}"#, &accessor));
+    }
+
+    #[test]
+    fn rejects_images_past_their_buffer() {
+        for offset in &["36", "1.8446744073709552e19"] {
+            let json = format!(
+                r#"{{"asset": {{"version": "2.0"}}, "nodes": [{{"mesh": 0}}],
+                    "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "material": 0}}]}}],
+                    "materials": [{{"pbrMetallicRoughness": {{"baseColorTexture": {{"index": 0}}}}}}],
+                    "textures": [{{"source": 0}}], "images": [{{"bufferView": 1}}],
+                    "accessors": [{}],
+                    "bufferViews": [{{"buffer": 0, "byteLength": 36}},
+                        {{"buffer": 0, "byteOffset": {}, "byteLength": 8}}],
+                    "buffers": [{{"byteLength": 36, "uri":
+                        "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"}}]}}"#,
+                TRIANGLE, offset
+            );
+            let result = GltfScene::from_gltf_with(json.as_bytes(), |_| Ok(Vec::new()));
+            let what = invalid(result);
+            assert!(what.contains("images[0]"), "{}", what);
+        }
+    }
+
+    #[test]
+    fn rejects_accessors_without_data() {
+        let accessor = r#"{"componentType": 5126, "count": 1000000000000, "type": "VEC3"}"#;
+        match scene(r#"{"mesh": 0}"#, accessor) {
+            Err(GltfError::Unsupported(_)) => {}
+            _ => panic!("expected an unsupported glTF error"),
+        }
+    }
+
+    #[test]
+    fn rejects_cycles_and_shared_nodes() {
+        let what = invalid(scene(
+            r#"{"children": [1]}, {"children": [0]}], "scenes": [{"nodes": [0]}"#,
+            TRIANGLE,
+        ));
+        assert!(what.contains("nodes[0]"), "{}", what);
+        let shared = r#"{"children": [2]}, {"children": [2]}, {"mesh": 0}"#;
+        invalid(scene(shared, TRIANGLE));
+    }
+
+    #[test]
+    fn reads_deep_hierarchies() {
+        let depth = 20_000;
+        let nodes: Vec<String> = (1..depth)
+            .map(|i| format!(r#"{{"children": [{}], "translation": [0, 0, 1]}}"#, i))
+            .chain(Some(r#"{"mesh": 0}"#.to_owned()))
+            .collect();
+        let scene = scene(&nodes.join(","), TRIANGLE).unwrap();
+        let v = scene.models[0].model.fvert(0, 0);
+        assert_eq!(v.z, (depth - 1) as f64);
+    }
+}
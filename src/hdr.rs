@@ -0,0 +1,505 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! [Radiance HDR] support, the usual format for high dynamic range images
+//! and environment maps. Pixels are stored as RGBE: three 8 bit mantissas
+//! sharing an 8 bit exponent.
+//!
+//! [Radiance HDR]: https://en.wikipedia.org/wiki/RGBE_image_format
+//!
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+
+use crate::image::ColorSpace;
+use crate::image::Image;
+use crate::image::Pixel;
+
+impl<P: Pixel> Image<P> {
+    /// Parse the given Radiance HDR data into a bitmap image.
+    ///
+    /// Flat, run-length encoded, and old style run-length encoded scanlines
+    /// are supported, in images stored top row or bottom row first.
+    /// Pixel values are divided by the `EXPOSURE` in the header, if there is one,
+    /// to get back the radiance. The image is tagged as linear.
+    pub fn from_hdr<R: Read>(data: &mut R) -> Result<Image<P>, HdrError> {
+        let mut bytes = Vec::new();
+        data.read_to_end(&mut bytes).map_err(HdrError::Io)?;
+        let mut parser = HdrParser {
+            data: &bytes,
+            pos: 0,
+        };
+
+        let exposure = parser.header()?;
+        let (width, height, bottom_up) = parser.resolution()?;
+
+        // run-length encoding lets small files have huge images,
+        // so only make room for the rows and pixels that are really there
+        let mut pixels = Vec::new();
+        let mut scanline = Vec::new();
+        for r in 0..height {
+            parser.scanline(r, width, &mut scanline)?;
+            pixels.extend(scanline.iter().map(|rgbe| {
+                let [r, g, b] = from_rgbe(*rgbe);
+                P::from_rgbaf([r / exposure, g / exposure, b / exposure, 1.0])
+            }));
+        }
+        // we always keep the top row first
+        if bottom_up {
+            for r in 0..height / 2 {
+                let (top, bottom) = pixels.split_at_mut((height - r - 1) * width);
+                top[r * width..(r + 1) * width].swap_with_slice(&mut bottom[..width]);
+            }
+        }
+
+        Ok(Image {
+            width,
+            height,
+            // remember if rows were counted from the bottom in the file
+            flipped: bottom_up,
+            color_space: ColorSpace::Linear,
+            pixels,
+        })
+    }
+
+    /// Save the bitmap data as a run-length encoded Radiance HDR image,
+    /// in linear light whatever the image's color space. Alpha is dropped.
+    /// Flipped images are stored bottom row first, others top row first,
+    /// so that `from_hdr` gives back the same image.
+    pub fn write_hdr<W: Write>(&self, writer: &mut BufWriter<W>) -> io::Result<()> {
+        writeln!(writer, "#?RADIANCE")?;
+        writeln!(writer, "FORMAT=32-bit_rle_rgbe")?;
+        writeln!(writer)?;
+        let y = if self.flipped { "+Y" } else { "-Y" };
+        writeln!(writer, "{} {} +X {}", y, self.height, self.width)?;
+
+        let mut scanline = Vec::with_capacity(self.width);
+        for r in 0..self.height {
+            let i = if self.flipped { self.height - r - 1 } else { r };
+            let row = &self.pixels[i * self.width..(i + 1) * self.width];
+            scanline.clear();
+            scanline.extend(row.iter().map(|p| {
+                let [r, g, b, _] = self.color_space.to_linear(p.to_rgbaf());
+                to_rgbe([r, g, b])
+            }));
+            write_scanline(writer, &scanline)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why Radiance HDR data could not be decoded.
+#[derive(Debug)]
+pub enum HdrError {
+    /// the data could not be read
+    Io(io::Error),
+    /// the data does not start with `#?RADIANCE` or `#?RGBE`
+    BadSignature,
+    /// the pixel format is not RGBE, the only one we read
+    UnsupportedFormat(String),
+    /// a header line is not a valid value
+    BadHeader(String),
+    /// the resolution line is not one we understand, we read
+    /// rows going down or up, each going left to right
+    BadResolution(String),
+    /// a run-length encoded scanline is longer than the image is wide,
+    /// rows are counted in the order they are stored
+    RleOverrun { row: usize },
+    /// the data ends before all pixels are read
+    Truncated,
+}
+
+impl fmt::Display for HdrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HdrError::Io(e) => write!(f, "cannot read Radiance HDR data: {}", e),
+            HdrError::BadSignature => write!(f, "not a Radiance HDR image"),
+            HdrError::UnsupportedFormat(format) => {
+                write!(f, "unsupported pixel format {:?}", format)
+            }
+            HdrError::BadHeader(line) => write!(f, "bad header line {:?}", line),
+            HdrError::BadResolution(line) => write!(f, "unsupported resolution {:?}", line),
+            HdrError::RleOverrun { row } => {
+                write!(f, "run-length encoding runs past the end of row {}", row)
+            }
+            HdrError::Truncated => write!(f, "truncated pixel data"),
+        }
+    }
+}
+
+impl error::Error for HdrError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            HdrError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Decode an RGBE pixel, the exponent scaling all three mantissas.
+fn from_rgbe(rgbe: [u8; 4]) -> [f64; 3] {
+    let [r, g, b, e] = rgbe;
+    if e == 0 {
+        return [0.0; 3];
+    }
+    // the mantissas are fractions of 256, and the exponent is offset by 128
+    let scale = 2f64.powi(e as i32 - 136);
+    [
+        (r as f64 + 0.5) * scale,
+        (g as f64 + 0.5) * scale,
+        (b as f64 + 0.5) * scale,
+    ]
+}
+
+/// Encode a color as RGBE, negative channels become 0.
+fn to_rgbe(rgb: [f64; 3]) -> [u8; 4] {
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    if max < 1e-32 || !max.is_finite() {
+        return [0; 4];
+    }
+    // the exponent that makes max fall in 0.5..1.0
+    let exponent = max.log2().floor() as i32 + 1;
+    if exponent > 127 {
+        return [255, 255, 255, 255];
+    }
+    let scale = 256.0 / 2f64.powi(exponent);
+    let mantissa = |c: f64| (c.max(0.0) * scale).min(255.0) as u8;
+    [
+        mantissa(rgb[0]),
+        mantissa(rgb[1]),
+        mantissa(rgb[2]),
+        (exponent + 128) as u8,
+    ]
+}
+
+/// Write a scanline, run-length encoded one channel at a time
+/// if the width allows it, flat otherwise.
+fn write_scanline<W: Write>(writer: &mut W, scanline: &[[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    if !(MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) {
+        for rgbe in scanline {
+            writer.write_all(rgbe)?;
+        }
+        return Ok(());
+    }
+
+    writer.write_all(&[2, 2, (width >> 8) as u8, width as u8])?;
+    let mut channel = Vec::with_capacity(width);
+    for c in 0..4 {
+        channel.clear();
+        channel.extend(scanline.iter().map(|rgbe| rgbe[c]));
+        write_channel_rle(writer, &channel)?;
+    }
+    Ok(())
+}
+
+/// Encode a channel as runs (128 + count, value) and literal dumps
+/// (count, values), with at most 127 and 128 bytes each.
+fn write_channel_rle<W: Write>(writer: &mut W, channel: &[u8]) -> io::Result<()> {
+    // runs shorter than this are cheaper as part of a dump
+    const MIN_RUN: usize = 3;
+    const MAX_RUN: usize = 127;
+    const MAX_DUMP: usize = 128;
+    let run_at = |pos: usize| {
+        channel[pos..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|c| **c == channel[pos])
+            .count()
+    };
+
+    let mut pos = 0;
+    while pos < channel.len() {
+        let run = run_at(pos);
+        if run >= MIN_RUN {
+            writer.write_all(&[128 + run as u8, channel[pos]])?;
+            pos += run;
+        } else {
+            // dump until the next run worth encoding starts
+            let mut end = pos + 1;
+            while end < channel.len() && end - pos < MAX_DUMP && run_at(end) < MIN_RUN {
+                end += 1;
+            }
+            writer.write_all(&[(end - pos) as u8])?;
+            writer.write_all(&channel[pos..end])?;
+            pos = end;
+        }
+    }
+    Ok(())
+}
+
+/// Run-length encoded scanlines must be at least this wide, and no wider than the max.
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7fff;
+
+struct HdrParser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> HdrParser<'a> {
+    /// The next line, without its newline.
+    fn line(&mut self) -> Result<&'a str, HdrError> {
+        let rest = &self.data[self.pos..];
+        let end = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or(HdrError::Truncated)?;
+        self.pos += end + 1;
+        let line = &rest[..end];
+        std::str::from_utf8(line)
+            .map_err(|_| HdrError::BadHeader(String::from_utf8_lossy(line).into_owned()))
+    }
+
+    /// Read the header lines up to the blank line ending them,
+    /// returning the combined exposure.
+    fn header(&mut self) -> Result<f64, HdrError> {
+        let signature = self.line().map_err(|_| HdrError::BadSignature)?;
+        if signature != "#?RADIANCE" && signature != "#?RGBE" {
+            return Err(HdrError::BadSignature);
+        }
+        let mut exposure = 1.0;
+        loop {
+            let line = self.line()?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format.trim() != "32-bit_rle_rgbe" {
+                    return Err(HdrError::UnsupportedFormat(format.to_owned()));
+                }
+            } else if let Some(value) = line.strip_prefix("EXPOSURE=") {
+                // exposures accumulate, each one scaled the pixels again
+                match value.trim().parse::<f64>() {
+                    Ok(e) if e > 0.0 => exposure *= e,
+                    _ => return Err(HdrError::BadHeader(line.to_owned())),
+                }
+            }
+            // comments and other variables, like GAMMA or PRIMARIES, are ignored
+        }
+        Ok(exposure)
+    }
+
+    /// Read the resolution line, returning the width, height,
+    /// and whether the bottom row comes first.
+    fn resolution(&mut self) -> Result<(usize, usize, bool), HdrError> {
+        let line = self.line()?;
+        let bad = || HdrError::BadResolution(line.to_owned());
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (y, height, x, width) = match fields[..] {
+            [y, height, x, width] => (y, height, x, width),
+            _ => return Err(bad()),
+        };
+        let bottom_up = match y {
+            "-Y" => false,
+            "+Y" => true,
+            _ => return Err(bad()),
+        };
+        if x != "+X" {
+            return Err(bad());
+        }
+        let height: usize = height.parse().map_err(|_| bad())?;
+        let width: usize = width.parse().map_err(|_| bad())?;
+        if width == 0 || height == 0 || width > 1 << 24 || height > 1 << 24 {
+            return Err(bad());
+        }
+        Ok((width, height, bottom_up))
+    }
+
+    fn byte(&mut self) -> Result<u8, HdrError> {
+        let b = *self.data.get(self.pos).ok_or(HdrError::Truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn rgbe(&mut self) -> Result<[u8; 4], HdrError> {
+        Ok([self.byte()?, self.byte()?, self.byte()?, self.byte()?])
+    }
+
+    /// Read scanline number `row`, in whichever encoding it uses.
+    fn scanline(
+        &mut self,
+        row: usize,
+        width: usize,
+        scanline: &mut Vec<[u8; 4]>,
+    ) -> Result<(), HdrError> {
+        scanline.clear();
+        let start = self.data.get(self.pos..self.pos + 4);
+        // new style scanlines start with 2, 2, and the width, which no pixel would
+        let is_rle = match start {
+            Some(&[2, 2, hi, lo]) => {
+                hi & 0x80 == 0
+                    && (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width)
+                    && ((hi as usize) << 8 | lo as usize) == width
+            }
+            _ => false,
+        };
+        if is_rle {
+            self.pos += 4;
+            // these are at most MAX_RLE_WIDTH wide
+            scanline.resize(width, [0; 4]);
+            self.scanline_rle(row, scanline)
+        } else {
+            self.scanline_flat(row, width, scanline)
+        }
+    }
+
+    /// Read a scanline stored one run-length encoded channel at a time.
+    fn scanline_rle(&mut self, row: usize, scanline: &mut [[u8; 4]]) -> Result<(), HdrError> {
+        for c in 0..4 {
+            let mut x = 0;
+            while x < scanline.len() {
+                let count = self.byte()? as usize;
+                if count > 128 {
+                    // a run of one value
+                    let count = count - 128;
+                    if x + count > scanline.len() {
+                        return Err(HdrError::RleOverrun { row });
+                    }
+                    let value = self.byte()?;
+                    for rgbe in &mut scanline[x..x + count] {
+                        rgbe[c] = value;
+                    }
+                    x += count;
+                } else {
+                    // a dump of different values
+                    if count == 0 || x + count > scanline.len() {
+                        return Err(HdrError::RleOverrun { row });
+                    }
+                    for rgbe in &mut scanline[x..x + count] {
+                        rgbe[c] = self.byte()?;
+                    }
+                    x += count;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a scanline of RGBE pixels, where 1, 1, 1 means repeating the
+    /// pixel before, as many times as the exponent says.
+    /// The scanline grows as pixels are read, as flat rows can be much wider.
+    fn scanline_flat(
+        &mut self,
+        row: usize,
+        width: usize,
+        scanline: &mut Vec<[u8; 4]>,
+    ) -> Result<(), HdrError> {
+        // consecutive repeats count in higher bytes
+        let mut shift = 0;
+        while scanline.len() < width {
+            let rgbe = self.rgbe()?;
+            match scanline.last() {
+                Some(&previous) if rgbe[..3] == [1, 1, 1] => {
+                    if shift > 24 {
+                        return Err(HdrError::RleOverrun { row });
+                    }
+                    // a few bytes can repeat a pixel billions of times,
+                    // so check it fits in the row before making room for it
+                    let count = (rgbe[3] as usize) << shift;
+                    if count > width - scanline.len() {
+                        return Err(HdrError::RleOverrun { row });
+                    }
+                    scanline.resize(scanline.len() + count, previous);
+                    shift += 8;
+                }
+                _ => {
+                    scanline.push(rgbe);
+                    shift = 0;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::HdrColor;
+
+    #[test]
+    fn round_trips_both_row_orders() {
+        for &(width, height) in &[(20, 5), (3, 4)] {
+            for &flipped in &[false, true] {
+                let mut image: Image<HdrColor> = Image::filled(width, height, [0.0; 3]);
+                image.color_space = ColorSpace::Linear;
+                image.flipped = flipped;
+                for (i, pixel) in image.pixels.iter_mut().enumerate() {
+                    *pixel = [(i % width) as f32, (i / width) as f32, 1.0];
+                }
+                let mut writer = BufWriter::new(Vec::new());
+                image.write_hdr(&mut writer).unwrap();
+                let data = writer.into_inner().unwrap();
+
+                let read: Image<HdrColor> = Image::from_hdr(&mut data.as_slice()).unwrap();
+                assert_eq!(
+                    (read.width, read.height, read.flipped),
+                    (width, height, flipped)
+                );
+                // a pixel's components share an exponent, so they are as precise as its largest
+                let close = |a: &HdrColor, b: &HdrColor| {
+                    let step = b.iter().fold(0.0f32, |m, &c| m.max(c)) / 128.0;
+                    a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() <= step)
+                };
+                let same = read
+                    .pixels
+                    .iter()
+                    .zip(image.pixels.iter())
+                    .all(|(a, b)| close(a, b));
+                assert!(same, "{}x{} flipped {}", width, height, flipped);
+            }
+        }
+    }
+
+    #[test]
+    fn huge_sizes_need_their_rows() {
+        let data =
+            b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 16777216 +X 16777216\n\x01\x02\x03\x04";
+        match Image::<HdrColor>::from_hdr(&mut &data[..]) {
+            Err(HdrError::Truncated) => {}
+            other => panic!("expected truncated data, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn repeats_must_fit_in_their_row() {
+        // one pixel, then repeats of 0, 0, 0, and 255 << 24 pixels, in a row 2^24 wide
+        let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 16777216\n".to_vec();
+        data.extend_from_slice(&[128, 128, 128, 129]);
+        for &count in &[0, 0, 0, 255] {
+            data.extend_from_slice(&[1, 1, 1, count]);
+        }
+        match Image::<HdrColor>::from_hdr(&mut data.as_slice()) {
+            Err(HdrError::RleOverrun { row: 0 }) => {}
+            other => panic!("expected an overrun, got {:?}", other.err()),
+        }
+        // while a run just filling the row is fine, and one more pixel is not
+        let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 4\n".to_vec();
+        data.extend_from_slice(&[128, 128, 128, 129, 1, 1, 1, 3]);
+        let image = Image::<HdrColor>::from_hdr(&mut data.as_slice()).unwrap();
+        assert_eq!(image.pixels, vec![image.pixels[0]; 4]);
+        data.pop();
+        data.push(4);
+        match Image::<HdrColor>::from_hdr(&mut data.as_slice()) {
+            Err(HdrError::RleOverrun { row: 0 }) => {}
+            other => panic!("expected an overrun, got {:?}", other.err()),
+        }
+    }
+}
@@ -16,27 +16,190 @@
 //! Bitmaps and simple operations on them
 //!
 
+use crate::srgb;
+
 pub type Color = [u8; 3];
 
+/// A color with an alpha channel, 0 being fully transparent and 255 fully opaque
+pub type Rgba = [u8; 4];
+
+/// A high dynamic range color, 1.0 being what `Color` shows as 255,
+/// but with room for brighter values and finer steps
+pub type HdrColor = [f32; 3];
+
+/// A color with alpha as floats, for filtering and blending,
+/// 1.0 being full intensity and fully opaque (HDR colors may go above)
+pub type Rgbaf = [f64; 4];
+
+/// How the color values of an image relate to how bright they are.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+    /// encoded with the sRGB transfer function, like most 8 bit images
+    Srgb,
+    /// proportional to the light, which is what shading math expects
+    Linear,
+}
+
+impl ColorSpace {
+    /// Turn colors stored in this color space into linear light, alpha is left alone.
+    pub fn to_linear(self, rgbaf: Rgbaf) -> Rgbaf {
+        match self {
+            ColorSpace::Srgb => {
+                let [r, g, b, a] = rgbaf;
+                [srgb::decode(r), srgb::decode(g), srgb::decode(b), a]
+            }
+            ColorSpace::Linear => rgbaf,
+        }
+    }
+
+    /// Turn linear light into colors stored in this color space, alpha is left alone.
+    pub fn from_linear(self, rgbaf: Rgbaf) -> Rgbaf {
+        match self {
+            ColorSpace::Srgb => {
+                let [r, g, b, a] = rgbaf;
+                [srgb::encode(r), srgb::encode(g), srgb::encode(b), a]
+            }
+            ColorSpace::Linear => rgbaf,
+        }
+    }
+}
+
+/// The kinds of pixels an `Image` can hold
+pub trait Pixel: Copy + Default + PartialEq {
+    /// how many channels there are, 3 for `Color` and 4 for `Rgba`
+    const CHANNELS: usize;
+
+    /// the color space images of these pixels are in, unless told otherwise
+    const COLOR_SPACE: ColorSpace;
+
+    /// convert from a color with alpha, dropping the alpha if there is no channel for it
+    fn from_rgba(rgba: Rgba) -> Self;
+
+    /// convert to a color with alpha, fully opaque if there is no alpha channel
+    fn to_rgba(self) -> Rgba;
+
+    /// convert from float channels, rounding and clamping them if they don't fit
+    fn from_rgbaf(rgbaf: Rgbaf) -> Self;
+
+    /// convert to float channels, fully opaque if there is no alpha channel
+    fn to_rgbaf(self) -> Rgbaf;
+}
+
+/// Scale a float channel to a byte, clamping it to 0.0..=1.0.
+fn to_byte(c: f64) -> u8 {
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+impl Pixel for Color {
+    const CHANNELS: usize = 3;
+    const COLOR_SPACE: ColorSpace = ColorSpace::Srgb;
+
+    fn from_rgba(rgba: Rgba) -> Color {
+        [rgba[0], rgba[1], rgba[2]]
+    }
+
+    fn to_rgba(self) -> Rgba {
+        [self[0], self[1], self[2], 255]
+    }
+
+    fn from_rgbaf(rgbaf: Rgbaf) -> Color {
+        [to_byte(rgbaf[0]), to_byte(rgbaf[1]), to_byte(rgbaf[2])]
+    }
+
+    fn to_rgbaf(self) -> Rgbaf {
+        Rgba::to_rgbaf(self.to_rgba())
+    }
+}
+
+impl Pixel for Rgba {
+    const CHANNELS: usize = 4;
+    const COLOR_SPACE: ColorSpace = ColorSpace::Srgb;
+
+    fn from_rgba(rgba: Rgba) -> Rgba {
+        rgba
+    }
+
+    fn to_rgba(self) -> Rgba {
+        self
+    }
+
+    fn from_rgbaf(rgbaf: Rgbaf) -> Rgba {
+        let [r, g, b, a] = rgbaf;
+        [to_byte(r), to_byte(g), to_byte(b), to_byte(a)]
+    }
+
+    fn to_rgbaf(self) -> Rgbaf {
+        let [r, g, b, a] = self;
+        [
+            r as f64 / 255.0,
+            g as f64 / 255.0,
+            b as f64 / 255.0,
+            a as f64 / 255.0,
+        ]
+    }
+}
+
+impl Pixel for HdrColor {
+    const CHANNELS: usize = 3;
+    const COLOR_SPACE: ColorSpace = ColorSpace::Linear;
+
+    fn from_rgba(rgba: Rgba) -> HdrColor {
+        HdrColor::from_rgbaf(rgba.to_rgbaf())
+    }
+
+    fn to_rgba(self) -> Rgba {
+        Rgba::from_rgbaf(self.to_rgbaf())
+    }
+
+    fn from_rgbaf(rgbaf: Rgbaf) -> HdrColor {
+        [rgbaf[0] as f32, rgbaf[1] as f32, rgbaf[2] as f32]
+    }
+
+    fn to_rgbaf(self) -> Rgbaf {
+        [self[0] as f64, self[1] as f64, self[2] as f64, 1.0]
+    }
+}
+
 /// An Image is a Vector of pixel colors with some
-/// metadata describing how to interpret the pixels
-pub struct Image {
+/// metadata describing how to interpret the pixels,
+/// the pixels are `Color`s unless something else is asked for
+#[derive(Clone)]
+pub struct Image<P = Color> {
     /// how many pixels are in each bitmap row
     pub width: usize,
     /// how many rows are in the bitmap
     pub height: usize,
-    /// if true, the first pixel row in the vector is the last row of the bitmap
+    /// if true, y coordinates count rows from the bottom of the bitmap, not the top
     pub flipped: bool,
-    pub pixels: Vec<Color>,
+    /// how the pixel values relate to brightness, samplers and `triangle` use it
+    /// to shade in linear light
+    pub color_space: ColorSpace,
+    /// the pixels of each row, left to right, the top row first (whether flipped or not)
+    pub pixels: Vec<P>,
 }
 
+/// An image with an alpha channel
+pub type RgbaImage = Image<Rgba>;
+
+/// A high dynamic range image, see `ToneMapper` for turning it into an `Image`
+pub type HdrImage = Image<HdrColor>;
+
 impl Image {
     pub fn make(w: usize, h: usize) -> Image {
+        Image::filled(w, h, [0, 0, 0])
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    /// an image with every pixel set to `color`,
+    /// in the usual color space for its pixels
+    pub fn filled(w: usize, h: usize, color: P) -> Image<P> {
         Image {
             width: w,
             height: h,
             flipped: false,
-            pixels: vec![[0, 0, 0]; w * h],
+            color_space: P::COLOR_SPACE,
+            pixels: vec![color; w * h],
         }
     }
 
@@ -50,24 +213,58 @@ impl Image {
         self.flipped = !self.flipped;
     }
 
-    pub fn put(&mut self, x: usize, y: usize, color: Color) {
-        let real_y = if self.flipped { self.height - y } else { y };
-        let pixel_index = x % self.width + real_y * self.width;
-        if (0..self.pixels.len()).contains(&pixel_index) {
-            self.pixels[pixel_index] = color;
+    /// set a pixel, ignoring coordinates outside the bitmap
+    pub fn put(&mut self, x: usize, y: usize, color: P) {
+        if x >= self.width || y >= self.height {
+            return;
         }
+        let pixel_index = x + self.row_index(y) * self.width;
+        self.pixels[pixel_index] = color;
     }
 
-    pub fn get(&self, x: usize, y: usize) -> Color {
-        let y = if self.flipped { self.height - y } else { y };
-        let pixel_index = x % self.width + y * self.width;
+    pub fn get(&self, x: usize, y: usize) -> P {
+        let pixel_index = x % self.width + self.row_index(y) * self.width;
         self.pixels[pixel_index]
     }
 
-    /// get a pixel using float coordinates between 0.0 and 1.0
-    pub fn get_unit(&self, x: f64, y: f64) -> Color {
-        let x = (x * self.width as f64) as usize;
-        let y = (y * self.height as f64) as usize;
+    /// the pixel rows, top row first (whether flipped or not)
+    pub fn rows(&self) -> std::slice::Chunks<'_, P> {
+        self.pixels.chunks(self.width.max(1))
+    }
+
+    /// which row in the pixel vector has the y coordinate `y`
+    fn row_index(&self, y: usize) -> usize {
+        if self.flipped {
+            self.height - 1 - y
+        } else {
+            y
+        }
+    }
+
+    /// get a pixel using float coordinates between 0.0 and 1.0,
+    /// coordinates outside that range wrap around,
+    /// see `Sampler` for smoother ways to look up colors in textures
+    pub fn get_unit(&self, x: f64, y: f64) -> P {
+        let x = (x * self.width as f64).floor() as i64;
+        let y = (y * self.height as f64).floor() as i64;
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
         self.get(x, y)
     }
+
+    /// a copy with another kind of pixels, with the same values and color space,
+    /// alpha is dropped or set to fully opaque as needed
+    pub fn convert<Q: Pixel>(&self) -> Image<Q> {
+        Image {
+            width: self.width,
+            height: self.height,
+            flipped: self.flipped,
+            color_space: self.color_space,
+            pixels: self
+                .pixels
+                .iter()
+                .map(|p| Q::from_rgba(p.to_rgba()))
+                .collect(),
+        }
+    }
 }
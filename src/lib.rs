@@ -12,12 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod envmap;
 pub mod geometry;
+pub mod gltf;
+pub mod hdr;
 pub mod image;
+pub mod json;
+pub mod mipmap;
 pub mod model;
+pub mod mtl;
 pub mod pbm;
+pub mod ply;
+pub mod png;
 pub mod render;
+pub mod sampler;
 pub mod shaders;
+pub mod srgb;
+pub mod stl;
 pub mod tga;
+pub mod tonemap;
+pub mod zlib;
 
 // How's this for a threshold of originality?
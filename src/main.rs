@@ -19,10 +19,15 @@ use std::fs::File;
 use std::io::stdout;
 use std::io::BufReader;
 use std::io::BufWriter;
+use std::path::Path;
 use std::time::Instant;
 
+use tinyrenderer::geometry::Aabb;
 use tinyrenderer::geometry::Vec3f;
+use tinyrenderer::gltf::GltfScene;
+use tinyrenderer::image::ColorSpace;
 use tinyrenderer::image::Image;
+use tinyrenderer::mipmap::MipChain;
 use tinyrenderer::model::Model;
 
 use tinyrenderer::render::lookat;
@@ -32,6 +37,7 @@ use tinyrenderer::render::viewport;
 
 use tinyrenderer::render::Shader;
 use tinyrenderer::shaders::GouraudShader;
+use tinyrenderer::shaders::MaterialBinding;
 
 // I wanted to macro, don't judge!
 macro_rules! f {
@@ -45,21 +51,49 @@ fn main() {
     args.next(); // we don't care about the command name, shift it
     let model_obj = args
         .next()
-        .expect("argv[1] should be an obj file with the model");
-    let texture_tga = args
+        .expect("argv[1] should be an OBJ, STL, PLY, or glTF file with the model");
+    let texture_file = args
         .next()
-        .expect("argv[2] should be a TGA file with the texture");
+        .expect("argv[2] should be a TGA or PNG file with the texture, or - for none");
     let width = args
         .next()
         .unwrap_or_else(|| "800".to_owned())
         .parse()
         .expect("argv[3] should be the image size");
     let height = width;
+    let format = args.next().unwrap_or_else(|| "pnm".to_owned());
+    // "legacy" shades the stored texture values, like before renders used linear light
+    let color_space = match args.next().as_deref() {
+        None | Some("srgb") => ColorSpace::Srgb,
+        Some("legacy") => ColorSpace::Linear,
+        _ => panic!("argv[5] should be the shading mode, srgb or legacy"),
+    };
 
     // load resources
-    let head = load_obj(model_obj.as_str());
-    let texture_image = load_tga(texture_tga.as_str());
-    eprintln!("model has {} faces", head.nfaces());
+    let mut parts = load_model(model_obj.as_str());
+    // the camera is set up for models in [-1, 1], which are drawn as they are,
+    // others are fit in the unit sphere, all of which the camera sees, together
+    let bounds = parts
+        .iter()
+        .filter_map(|(model, _)| model.bounds())
+        .reduce(|a, b| a.union(&b));
+    let fits = |b: &Aabb| (0..3).all(|i| b.min[i] >= -1.0 && b.max[i] <= 1.0);
+    if let Some(bounds) = bounds.filter(|b| !fits(b)) {
+        let to_unit_sphere = bounds.to_unit_sphere();
+        for (model, _) in &mut parts {
+            model.transform(&to_unit_sphere);
+        }
+    }
+    // faces without a textured material use this one, white if there is none
+    let mut texture_image = if texture_file == "-" {
+        Image::filled(1, 1, [255, 255, 255])
+    } else {
+        load_texture(Path::new(&texture_file))
+    };
+    texture_image.color_space = color_space;
+    let texture_mips = MipChain::new(&texture_image);
+    let nfaces: usize = parts.iter().map(|(model, _)| model.nfaces()).sum();
+    eprintln!("model has {} faces", nfaces);
 
     // setup scene
     // TODO read from TOML?
@@ -78,43 +112,138 @@ fn main() {
     );
     let vpmv = &view_port * &projection * &model_view;
 
-    let mut shader = GouraudShader::new(&head, &vpmv, &texture_image, light);
-
     let mut timer = Timer::default();
 
     // draw stuff!
     timer.start("rendering");
     let mut canvas = Image::make(width, height);
     canvas.flip();
-    let mut zbuffer = vec![std::f64::MIN; width * height];
-    for i in 0..(head.nfaces()) {
-        let v0 = shader.vertex(i, 0);
-        let v1 = shader.vertex(i, 1);
-        let v2 = shader.vertex(i, 2);
-        triangle(&mut canvas, &mut zbuffer, &mut shader, v0, v1, v2);
+    canvas.color_space = color_space;
+    let mut zbuffer = vec![f64::MIN; width * height];
+    for (head, own_texture) in &parts {
+        // parts of glTF scenes bring their own textures
+        let own_mips = own_texture.as_ref().map(|texture| {
+            let mut texture = texture.clone();
+            texture.color_space = color_space;
+            (MipChain::new(&texture), texture)
+        });
+        let (mips, texture) = match &own_mips {
+            Some((mips, texture)) => (mips, texture),
+            None => (&texture_mips, &texture_image),
+        };
+
+        // the textures of every material, mipmapped
+        let material_mips: Vec<_> = head
+            .materials()
+            .iter()
+            .map(|material| {
+                let diffuse = load_material_texture(material.diffuse_map.as_deref(), color_space);
+                // opacity values are not colors, so never decode them
+                let dissolve =
+                    load_material_texture(material.dissolve_map.as_deref(), ColorSpace::Linear);
+                (diffuse, dissolve)
+            })
+            .collect();
+        let materials: Vec<_> = head
+            .materials()
+            .iter()
+            .zip(material_mips.iter())
+            .map(|(material, (diffuse, dissolve))| MaterialBinding {
+                material,
+                diffuse: diffuse.as_ref(),
+                dissolve: dissolve.as_ref(),
+            })
+            .collect();
+
+        let mut shader = GouraudShader::new(head, &vpmv, texture, light)
+            .with_mipmaps(mips)
+            .with_materials(&materials);
+        // scans often come without a texture, with colored vertices instead
+        if head.has_colors() {
+            shader = shader.with_vertex_colors(color_space);
+        }
+        for i in 0..(head.nfaces()) {
+            let v0 = shader.vertex(i, 0);
+            let v1 = shader.vertex(i, 1);
+            let v2 = shader.vertex(i, 2);
+            triangle(&mut canvas, &mut zbuffer, &mut shader, v0, v1, v2);
+        }
     }
     timer.stop();
 
     timer.start("saving image");
     let mut writer = BufWriter::new(stdout());
-    canvas.write(&mut writer);
+    match format.as_str() {
+        "pnm" => canvas.write(&mut writer),
+        "png" => canvas
+            .write_png(&mut writer)
+            .expect("unable to write PNG image"),
+        _ => panic!("argv[4] should be the output format, pnm or png"),
+    }
     timer.stop();
 }
 
-fn load_obj(filename: &str) -> Model {
+/// Load the parts of a model, with the textures of their own they have.
+/// Only glTF scenes have several parts, or textures in them.
+fn load_model(filename: &str) -> Vec<(Model, Option<Image>)> {
     let mut t = Timer::default();
     t.start(&format!("loading model {}", filename));
-    let obj_file = File::open(filename).expect("unable to open OBJ file");
-    let model = Model::from_obj(BufReader::new(obj_file));
+    let lowercase = filename.to_lowercase();
+    if lowercase.ends_with(".gltf") || lowercase.ends_with(".glb") {
+        let scene = GltfScene::from_gltf_file(filename)
+            .unwrap_or_else(|e| panic!("cannot read glTF file: {}", e));
+        t.stop();
+        let textures = &scene.textures;
+        return scene
+            .models
+            .into_iter()
+            .map(|part| (part.model, part.texture.map(|i| textures[i].convert())))
+            .collect();
+    }
+    let model = if lowercase.ends_with(".stl") {
+        let mut stl_file = File::open(filename).expect("unable to open STL file");
+        Model::from_stl(&mut BufReader::new(&mut stl_file))
+            .unwrap_or_else(|e| panic!("cannot read STL file: {}", e))
+    } else if lowercase.ends_with(".ply") {
+        let mut ply_file = File::open(filename).expect("unable to open PLY file");
+        Model::from_ply(&mut BufReader::new(&mut ply_file))
+            .unwrap_or_else(|e| panic!("cannot read PLY file: {}", e))
+    } else {
+        Model::from_obj_file(filename).unwrap_or_else(|e| panic!("cannot read OBJ file: {}", e))
+    };
     t.stop();
-    model
+    for library in model.missing_material_libraries() {
+        eprintln!("skipping missing material library {}", library);
+    }
+    vec![(model, None)]
 }
 
-fn load_tga(filename: &str) -> Image {
+/// Load a texture a material uses, if it has one and the file exists,
+/// tagging it with the given color space.
+fn load_material_texture(path: Option<&Path>, color_space: ColorSpace) -> Option<MipChain> {
+    let path = path?;
+    if !path.exists() {
+        eprintln!("skipping missing texture {}", path.display());
+        return None;
+    }
+    let mut texture = load_texture(path);
+    texture.color_space = color_space;
+    Some(MipChain::new(&texture))
+}
+
+fn load_texture(filename: &Path) -> Image {
     let mut t = Timer::default();
-    t.start(&format!("loading texture {}", filename));
-    let mut texture_file = File::open(filename).expect("unable to open TGA file");
-    let texture = Image::from_tga(&mut texture_file);
+    t.start(&format!("loading texture {}", filename.display()));
+    let mut texture_file = File::open(filename).expect("unable to open texture file");
+    let is_png = filename
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("png"));
+    let texture = if is_png {
+        Image::from_png(&mut BufReader::new(texture_file)).expect("unable to read PNG file")
+    } else {
+        Image::from_tga(&mut texture_file)
+    };
     t.stop();
     texture
 }
@@ -124,12 +253,12 @@ struct Timer {
 }
 
 impl Timer {
-    fn start(self: &mut Self, message: &str) -> &mut Self {
+    fn start(&mut self, message: &str) -> &mut Self {
         eprint!("{}...", message);
         self.since = Instant::now();
         self
     }
-    fn stop(self: &Self) {
+    fn stop(&self) {
         eprintln!("...done ({}ms)", self.since.elapsed().as_millis());
     }
 }
@@ -0,0 +1,170 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! [Mipmaps], smaller and smaller copies of a texture,
+//! so that textures far away can be sampled without shimmering.
+//!
+//! [Mipmaps]: https://en.wikipedia.org/wiki/Mipmap
+//!
+
+use crate::image::Color;
+use crate::image::Image;
+use crate::image::Pixel;
+
+/// A texture and its mipmap levels, each half the size of the one before,
+/// down to 1x1.
+pub struct MipChain<P = Color> {
+    levels: Vec<Image<P>>,
+}
+
+impl<P: Pixel> MipChain<P> {
+    /// Build all levels from the image by box filtering, in linear light.
+    /// Sizes that are not a power of two are rounded down when halved,
+    /// with each texel averaging the part of the level above it covers.
+    pub fn new(image: &Image<P>) -> MipChain<P> {
+        let mut levels = vec![image.clone()];
+        loop {
+            let last = &levels[levels.len() - 1];
+            if last.width <= 1 && last.height <= 1 {
+                break;
+            }
+            let next = downsample(last, (last.width / 2).max(1), (last.height / 2).max(1));
+            levels.push(next);
+        }
+        MipChain { levels }
+    }
+
+    /// How many levels there are, including the full size one.
+    pub fn nlevels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Get a level by index, 0 being the full size texture.
+    pub fn level(&self, i: usize) -> &Image<P> {
+        &self.levels[i]
+    }
+}
+
+/// Box filter the image down to the given size.
+fn downsample<P: Pixel>(image: &Image<P>, width: usize, height: usize) -> Image<P> {
+    let xs = box_weights(image.width, width);
+    let ys = box_weights(image.height, height);
+    let mut result = Image::filled(width, height, P::default());
+    result.flipped = image.flipped;
+    result.color_space = image.color_space;
+    for (y, y_weights) in ys.iter().enumerate() {
+        for (x, x_weights) in xs.iter().enumerate() {
+            let mut sum = [0.0; 4];
+            for &(sy, wy) in y_weights {
+                for &(sx, wx) in x_weights {
+                    let texel = image.pixels[sx + sy * image.width].to_rgbaf();
+                    let texel = image.color_space.to_linear(texel);
+                    for (s, t) in sum.iter_mut().zip(texel.iter()) {
+                        *s += t * wx * wy;
+                    }
+                }
+            }
+            result.pixels[x + y * width] = P::from_rgbaf(image.color_space.from_linear(sum));
+        }
+    }
+    result
+}
+
+/// For each of the `to` texels, which of the `from` texels it covers and by how much.
+/// The weights for each texel add up to 1.
+fn box_weights(from: usize, to: usize) -> Vec<Vec<(usize, f64)>> {
+    let scale = from as f64 / to as f64;
+    (0..to)
+        .map(|i| {
+            let (start, end) = (i as f64 * scale, (i + 1) as f64 * scale);
+            let first = start.floor() as usize;
+            let last = (end.ceil() as usize).min(from);
+            (first..last)
+                .map(|s| {
+                    let covered = end.min(s as f64 + 1.0) - start.max(s as f64);
+                    (s, covered / scale)
+                })
+                .filter(|&(_, w)| w > 0.0)
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ColorSpace;
+    use crate::image::HdrColor;
+
+    fn sizes<P: Pixel>(mips: &MipChain<P>) -> Vec<(usize, usize)> {
+        (0..mips.nlevels())
+            .map(|i| (mips.level(i).width, mips.level(i).height))
+            .collect()
+    }
+
+    #[test]
+    fn halves_down_to_one_texel() {
+        let chain = |w, h| sizes(&MipChain::new(&Image::filled(w, h, [0, 0, 0])));
+        assert_eq!(chain(8, 8), [(8, 8), (4, 4), (2, 2), (1, 1)]);
+        assert_eq!(chain(5, 3), [(5, 3), (2, 1), (1, 1)]);
+        assert_eq!(chain(1, 4), [(1, 4), (1, 2), (1, 1)]);
+        assert_eq!(chain(1, 1), [(1, 1)]);
+    }
+
+    #[test]
+    fn box_weights_cover_their_texels() {
+        for &(from, to) in &[(4, 2), (5, 2), (3, 1), (7, 3), (1, 1)] {
+            let weights = box_weights(from, to);
+            assert_eq!(weights.len(), to);
+            for texel in &weights {
+                let sum: f64 = texel.iter().map(|&(_, w)| w).sum();
+                assert!((sum - 1.0).abs() < 1e-9, "{} to {}: {:?}", from, to, texel);
+            }
+            // and every texel is used as much as any other
+            let mut used = vec![0.0; from];
+            for &(s, w) in weights.iter().flatten() {
+                used[s] += w;
+            }
+            let share = to as f64 / from as f64;
+            assert!(used.iter().all(|u| (u - share).abs() < 1e-9), "{:?}", used);
+        }
+    }
+
+    #[test]
+    fn averages_odd_sizes() {
+        let mut image: Image<HdrColor> = Image::filled(5, 1, [0.0; 3]);
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            *pixel = [i as f32; 3];
+        }
+        let mips = MipChain::new(&image);
+        // the middle texel is split between the two halves
+        let grays: Vec<f32> = mips.level(1).pixels.iter().map(|p| p[0]).collect();
+        assert_eq!(grays.len(), 2);
+        assert!((grays[0] - 0.8).abs() < 1e-6 && (grays[1] - 3.2).abs() < 1e-6);
+        assert!((mips.level(2).pixels[0][0] - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn averages_in_linear_light() {
+        let mut image: Image = Image::filled(2, 1, [0, 0, 0]);
+        image.pixels[1] = [255, 255, 255];
+        image.flipped = true;
+        let mips = MipChain::new(&image);
+        let level = mips.level(1);
+        // half the light, not half the sRGB value
+        assert_eq!(level.pixels, [[188, 188, 188]]);
+        assert_eq!((level.flipped, level.color_space), (true, ColorSpace::Srgb));
+    }
+}
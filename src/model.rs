@@ -18,86 +18,590 @@
 //! [Wavefront .obj]: https://en.wikipedia.org/wiki/Wavefront_.obj_file
 //!
 
+use std::collections::HashMap;
+use std::error;
+use std::f64::consts::FRAC_PI_3;
+use std::fmt;
+use std::fs::File;
+use std::io;
 use std::io::BufRead;
-use std::str::FromStr;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+use std::str::SplitWhitespace;
 
+use crate::geometry::Aabb;
+use crate::geometry::Matrix;
 use crate::geometry::Vec3;
 use crate::geometry::Vec3f;
+use crate::image::Rgba;
+use crate::mtl::Material;
 
-/// A face references vertices, texture uv coords, and normals by index
+/// A face references vertices, texture uv coords, normals, and its material by index,
+/// faces without uv coords or normals in the file have `None`,
+/// like faces before the first `usemtl` have no material
 pub struct Face {
     pub verts: Vec3<usize>,
-    pub uvs: Vec3<usize>,
-    pub norms: Vec3<usize>,
+    pub uvs: Option<Vec3<usize>>,
+    pub norms: Option<Vec3<usize>>,
+    pub material: Option<usize>,
+    /// from `s`, generated normals are only smoothed between faces in the
+    /// same group, 0 (`s off`) means not smoothed, unless all faces are 0
+    pub smoothing_group: u32,
+}
+
+/// A named part of a model, from an `o` or `g` line up to the next one.
+/// A name can be used for several parts, `g` can give a part several names.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SubMesh {
+    pub name: String,
+    /// the indices of the faces in the part
+    pub faces: Range<usize>,
 }
 
 /// A model has a bunch of faces (triangles), see Face for what data they contain.
 /// The face data lives in the model, since multiple faces share data.
+/// Polygons with more than three vertices are split into triangles when read.
+/// Faces without normals get generated ones, see `NormalMode::default`.
 pub struct Model {
     verts: Vec<Vec3f>,
     uvs: Vec<Vec3f>,
     norms: Vec<Vec3f>,
     faces: Vec<Face>,
+    materials: Vec<Material>,
+    /// the names of the material libraries, as `mtllib` gave them
+    material_libraries: Vec<String>,
+    /// the names of the material libraries that could not be opened
+    missing_material_libraries: Vec<String>,
+    objects: Vec<SubMesh>,
+    groups: Vec<SubMesh>,
+    /// the tangent and handedness at each corner, three per face
+    tangents: Vec<(Vec3f, f64)>,
+    /// a color for each vertex, in sRGB, or empty if the vertices have none
+    colors: Vec<Rgba>,
 }
 
 impl Model {
-    /// Parse the given data as a Waveform .obj into a model
+    /// Parse the given data as a Waveform .obj into a model.
+    /// Panics if the data is not a valid .obj, see `try_from_obj`.
     pub fn from_obj<R: BufRead>(obj_data: R) -> Model {
+        Model::try_from_obj(obj_data).unwrap_or_else(|e| panic!("cannot parse OBJ data: {}", e))
+    }
+
+    /// Parse the given data as a Waveform .obj into a model,
+    /// reporting where and why parsing failed if the data is malformed.
+    /// Material libraries are not read, faces get the materials `usemtl`
+    /// names, but they all look like `Material::new`, see `try_from_obj_with`.
+    pub fn try_from_obj<R: BufRead>(obj_data: R) -> Result<Model, ObjError> {
+        Model::try_from_obj_with(obj_data, |_| Ok(io::empty()))
+    }
+
+    /// Read the .obj file at `path`, with the material libraries it uses,
+    /// which are looked for in the same directory, like the textures they use.
+    /// The texture paths of the materials are made relative to the working directory.
+    pub fn from_obj_file<P: AsRef<Path>>(path: P) -> Result<Model, ObjError> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let obj_file = File::open(path).map_err(ObjError::Io)?;
+        let mut model = Model::try_from_obj_with(BufReader::new(obj_file), |library| {
+            File::open(dir.join(library)).map(BufReader::new)
+        })?;
+        for material in &mut model.materials {
+            let mut maps = [
+                &mut material.diffuse_map,
+                &mut material.specular_map,
+                &mut material.bump_map,
+                &mut material.dissolve_map,
+            ];
+            for path in maps.iter_mut().flat_map(|map| map.iter_mut()) {
+                *path = dir.join(&path);
+            }
+        }
+        Ok(model)
+    }
+
+    /// Parse the given data as a Waveform .obj into a model, reading
+    /// the material libraries named by `mtllib` from what `open_library`
+    /// returns for their names. Libraries that can't be opened are skipped,
+    /// see `missing_material_libraries`, but malformed ones are an error.
+    pub fn try_from_obj_with<R, L, F>(obj_data: R, mut open_library: F) -> Result<Model, ObjError>
+    where
+        R: BufRead,
+        L: BufRead,
+        F: FnMut(&str) -> io::Result<L>,
+    {
+        let mut materials = MaterialTable::default();
+        let mut material_libraries = Vec::new();
+        let mut missing_material_libraries = Vec::new();
+        let mut current_material = None;
+        let mut smoothing_group = 0;
+        let mut objects = SubMeshes::default();
+        let mut groups = SubMeshes::default();
         let mut obj_verts = Vec::new();
         let mut obj_uvs = Vec::new();
         let mut obj_norms = Vec::new();
         let mut obj_faces = Vec::new();
-        for line in obj_data.lines() {
-            let line = line.expect("line cannot be empty");
-            let tokens = &mut line.split_whitespace();
-            match tokens.next() {
+        for (i, line) in obj_data.lines().enumerate() {
+            let text = line.map_err(ObjError::Io)?;
+            let line = &mut ObjLine::new(i + 1, &text);
+            match line.next() {
                 Some("v") => {
-                    let (x, y, z) = parse_triplet(tokens);
+                    let (x, y, z) = (line.float()?, line.float()?, line.float()?);
                     obj_verts.push(Vec3f { x, y, z });
                 }
                 Some("vt") => {
                     // not all obj files have 3 dimensions for the uv coords
-                    let (x, y, z) = (
-                        parse_next(tokens),
-                        parse_next(tokens),
-                        parse_next_or(tokens, 0.),
-                    );
+                    let (x, y, z) = (line.float()?, line.float()?, line.float_or(0.)?);
                     obj_uvs.push(Vec3f { x, y, z });
                 }
                 Some("vn") => {
-                    let (x, y, z) = parse_triplet(tokens);
+                    let (x, y, z) = (line.float()?, line.float()?, line.float()?);
                     obj_norms.push(Vec3f { x, y, z });
                 }
                 Some("f") => {
-                    let (mut a, mut b, mut c) = (
-                        tokens.next().expect("face missing v0").split('/'),
-                        tokens.next().expect("face missing v1").split('/'),
-                        tokens.next().expect("face missing v2").split('/'),
-                    );
-
-                    let (v0, uv0, n0): (usize, usize, usize) = parse_triplet(&mut a);
-                    let (v1, uv1, n1): (usize, usize, usize) = parse_triplet(&mut b);
-                    let (v2, uv2, n2): (usize, usize, usize) = parse_triplet(&mut c);
-
-                    // in wavefront obj indices start at 1, not 0
-                    let face = Face {
-                        verts: Vec3::new(v0 - 1, v1 - 1, v2 - 1),
-                        uvs: Vec3::new(uv0 - 1, uv1 - 1, uv2 - 1),
-                        norms: Vec3::new(n0 - 1, n1 - 1, n2 - 1),
+                    let counts = (obj_verts.len(), obj_uvs.len(), obj_norms.len());
+                    let refs = line.vertex_refs(counts)?;
+                    let points: Vec<Vec3f> = refs.iter().map(|r| obj_verts[r.0]).collect();
+                    for [a, b, c] in triangulate(&points) {
+                        // all vertices of a face have uvs and normals, or none do
+                        let (ra, rb, rc) = (refs[a], refs[b], refs[c]);
+                        let face = Face {
+                            verts: Vec3::new(ra.0, rb.0, rc.0),
+                            uvs: ra.1.map(|uv| Vec3::new(uv, rb.1.unwrap(), rc.1.unwrap())),
+                            norms: ra.2.map(|n| Vec3::new(n, rb.2.unwrap(), rc.2.unwrap())),
+                            material: current_material,
+                            smoothing_group,
+                        };
+                        obj_faces.push(face);
+                    }
+                }
+                Some("mtllib") => {
+                    while let Some(name) = line.next() {
+                        material_libraries.push(name.to_owned());
+                        // models often come without their materials, they can do without
+                        let library = match open_library(name) {
+                            Ok(library) => library,
+                            Err(_) => {
+                                missing_material_libraries.push(name.to_owned());
+                                continue;
+                            }
+                        };
+                        let library =
+                            Material::try_from_mtl(library).map_err(|e| ObjError::BadLibrary {
+                                line: line.number,
+                                column: line.column(name),
+                                name: name.to_owned(),
+                                error: Box::new(e),
+                            })?;
+                        for material in library {
+                            materials.define(material);
+                        }
+                    }
+                }
+                Some("usemtl") => {
+                    // a bare usemtl goes back to no material
+                    current_material = line.rest().map(|name| materials.index(name));
+                }
+                Some("o") => {
+                    let name = line.rest().ok_or_else(|| line.missing())?;
+                    objects.start(vec![name.to_owned()], obj_faces.len());
+                }
+                Some("g") => {
+                    let mut names: Vec<String> = Vec::new();
+                    while let Some(name) = line.next() {
+                        names.push(name.to_owned());
+                    }
+                    // a bare g goes back to the group faces are in by default
+                    if names.is_empty() {
+                        names.push("default".to_owned());
+                    }
+                    groups.start(names, obj_faces.len());
+                }
+                Some("s") => {
+                    smoothing_group = match line.next() {
+                        Some("off") => 0,
+                        Some(token) => token.parse().map_err(|_| ObjError::BadIndex {
+                            line: line.number,
+                            column: line.column(token),
+                            token: token.to_owned(),
+                        })?,
+                        None => return Err(line.missing()),
                     };
-                    obj_faces.push(face);
                 }
-                _ => continue,
+                Some(directive) if IGNORED_DIRECTIVES.contains(&directive) => continue,
+                Some(directive) if directive.starts_with('#') => continue,
+                Some(directive) => {
+                    return Err(ObjError::UnsupportedDirective {
+                        line: line.number,
+                        column: line.column(directive),
+                        token: directive.to_owned(),
+                    })
+                }
+                None => continue,
+            }
+        }
+        let obj_faces_len = obj_faces.len();
+        let mut model = Model::from_faces(
+            obj_verts,
+            obj_uvs,
+            obj_norms,
+            obj_faces,
+            NormalMode::default(),
+        );
+        model.materials = materials.materials;
+        model.material_libraries = material_libraries;
+        model.missing_material_libraries = missing_material_libraries;
+        model.objects = objects.finish(obj_faces_len);
+        model.groups = groups.finish(obj_faces_len);
+        Ok(model)
+    }
+
+    /// A model with the given data and no materials or parts,
+    /// generating normals for the faces that have none.
+    pub(crate) fn from_faces(
+        verts: Vec<Vec3f>,
+        uvs: Vec<Vec3f>,
+        norms: Vec<Vec3f>,
+        faces: Vec<Face>,
+        mode: NormalMode,
+    ) -> Model {
+        let mut model = Model {
+            verts,
+            uvs,
+            norms,
+            faces,
+            materials: Vec::new(),
+            material_libraries: Vec::new(),
+            missing_material_libraries: Vec::new(),
+            objects: Vec::new(),
+            groups: Vec::new(),
+            tangents: Vec::new(),
+            colors: Vec::new(),
+        };
+        model.fill_missing_normals(mode);
+        model.tangents = model.compute_tangents();
+        model
+    }
+
+    /// Save the model as a Wavefront .obj, with `precision` digits after the
+    /// decimal point for coordinates. Faces are written as triangles, with their
+    /// materials, objects, groups, and smoothing groups, so that `from_obj`
+    /// gives back the same model, give or take the precision.
+    /// Faces going back to no material get a bare `usemtl`, which `from_obj` reads as such.
+    /// Only the names of material libraries are written, not the libraries.
+    pub fn write_obj<W: Write>(
+        &self,
+        writer: &mut BufWriter<W>,
+        precision: usize,
+    ) -> io::Result<()> {
+        for library in &self.material_libraries {
+            writeln!(writer, "mtllib {}", library)?;
+        }
+        for v in &self.verts {
+            writeln!(
+                writer,
+                "v {:.*} {:.*} {:.*}",
+                precision, v.x, precision, v.y, precision, v.z
+            )?;
+        }
+        for uv in &self.uvs {
+            write!(writer, "vt {:.*} {:.*}", precision, uv.x, precision, uv.y)?;
+            if uv.z != 0.0 {
+                write!(writer, " {:.*}", precision, uv.z)?;
+            }
+            writeln!(writer)?;
+        }
+        for n in &self.norms {
+            writeln!(
+                writer,
+                "vn {:.*} {:.*} {:.*}",
+                precision, n.x, precision, n.y, precision, n.z
+            )?;
+        }
+
+        let (mut material, mut smoothing_group) = (None, 0);
+        for (f, face) in self.faces.iter().enumerate() {
+            write_part_starts(writer, "o", &self.objects, f)?;
+            write_part_starts(writer, "g", &self.groups, f)?;
+            if face.material != material {
+                match face.material {
+                    Some(m) => writeln!(writer, "usemtl {}", self.materials[m].name)?,
+                    None => writeln!(writer, "usemtl")?,
+                }
+                material = face.material;
+            }
+            if face.smoothing_group != smoothing_group {
+                match face.smoothing_group {
+                    0 => writeln!(writer, "s off")?,
+                    group => writeln!(writer, "s {}", group)?,
+                }
+                smoothing_group = face.smoothing_group;
+            }
+            write!(writer, "f")?;
+            for v in 0..3 {
+                // indices start at 1 in .obj files
+                write!(writer, " {}", face.verts[v] + 1)?;
+                match (face.uvs, face.norms) {
+                    (Some(uvs), Some(norms)) => write!(writer, "/{}/{}", uvs[v] + 1, norms[v] + 1)?,
+                    (Some(uvs), None) => write!(writer, "/{}", uvs[v] + 1)?,
+                    (None, Some(norms)) => write!(writer, "//{}", norms[v] + 1)?,
+                    (None, None) => {}
+                }
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+
+    /// Replace the normals of every face with generated ones,
+    /// for models whose normals are missing for some faces, or just bad.
+    pub fn generate_normals(&mut self, mode: NormalMode) {
+        self.norms.clear();
+        for face in &mut self.faces {
+            face.norms = None;
+        }
+        self.fill_missing_normals(mode);
+        self.tangents = self.compute_tangents();
+    }
+
+    /// Generate normals for the faces that have none,
+    /// faces with normals are left alone and don't affect the new normals.
+    fn fill_missing_normals(&mut self, mode: NormalMode) {
+        let missing: Vec<usize> = (0..self.faces.len())
+            .filter(|&f| self.faces[f].norms.is_none())
+            .collect();
+        // the normal of each face's plane, as long as twice its area
+        let crosses: Vec<Vec3f> = missing
+            .iter()
+            .map(|&f| {
+                let (a, b, c) = (self.fvert(f, 0), self.fvert(f, 1), self.fvert(f, 2));
+                (b - a).cross(c - a)
+            })
+            .collect();
+        let units: Vec<Vec3f> = crosses.iter().map(|n| normalized_or_zero(*n)).collect();
+
+        let generated: Vec<[Vec3f; 3]> = match mode {
+            NormalMode::Flat => crosses
+                .iter()
+                .map(|&n| {
+                    let n = plane_normal(n);
+                    [n, n, n]
+                })
+                .collect(),
+            NormalMode::Smooth {
+                weighting,
+                crease_angle,
+            } => self.smooth_normals(&missing, &crosses, &units, weighting, crease_angle),
+        };
+
+        // equal normals are only stored once
+        let mut indices = HashMap::new();
+        for (&f, face_norms) in missing.iter().zip(generated.iter()) {
+            let mut norm_indices = Vec3::new(0, 0, 0);
+            for (corner, n) in face_norms.iter().enumerate() {
+                let norms = &mut self.norms;
+                // adding zero makes -0 the same as 0
+                let key = [
+                    (n.x + 0.0).to_bits(),
+                    (n.y + 0.0).to_bits(),
+                    (n.z + 0.0).to_bits(),
+                ];
+                norm_indices[corner] = *indices.entry(key).or_insert_with(|| {
+                    norms.push(*n);
+                    norms.len() - 1
+                });
+            }
+            self.faces[f].norms = Some(norm_indices);
+        }
+    }
+
+    /// The normals at the corners of the `missing` faces, averaged over the
+    /// faces around each vertex, given each face's `cross` product and its `unit` normal.
+    fn smooth_normals(
+        &self,
+        missing: &[usize],
+        crosses: &[Vec3f],
+        units: &[Vec3f],
+        weighting: NormalWeighting,
+        crease_angle: f64,
+    ) -> Vec<[Vec3f; 3]> {
+        // the (missing face, corner) pairs around each vertex
+        let mut corners = vec![Vec::new(); self.verts.len()];
+        for (m, &f) in missing.iter().enumerate() {
+            for corner in 0..3 {
+                corners[self.faces[f].verts[corner]].push((m, corner));
+            }
+        }
+        let weight = |m: usize, corner: usize| match weighting {
+            NormalWeighting::Area => crosses[m].norm(),
+            NormalWeighting::Angle => {
+                let verts = self.faces[missing[m]].verts;
+                corner_angle(
+                    self.verts[verts[corner]],
+                    self.verts[verts[(corner + 1) % 3]],
+                    self.verts[verts[(corner + 2) % 3]],
+                )
+            }
+        };
+        let min_cos = crease_angle.cos();
+        // without any smoothing groups, smooth everything
+        let use_groups = missing.iter().any(|&f| self.faces[f].smoothing_group != 0);
+        let same_group = |a: usize, b: usize| {
+            let (a, b) = (&self.faces[missing[a]], &self.faces[missing[b]]);
+            !use_groups || (a.smoothing_group != 0 && a.smoothing_group == b.smoothing_group)
+        };
+        let mut generated = Vec::with_capacity(missing.len());
+        for (m, &f) in missing.iter().enumerate() {
+            let mut face_norms = [Vec3f::zero(); 3];
+            for (corner, n) in face_norms.iter_mut().enumerate() {
+                // only smooth across edges flatter than the crease angle, within
+                // smoothing groups, faces on either side of a hard edge get their own normals
+                let mut sum = Vec3f::zero();
+                for &(other, other_corner) in &corners[self.faces[f].verts[corner]] {
+                    if other == m || (same_group(m, other) && units[m] * units[other] >= min_cos) {
+                        sum = sum + units[other] * weight(other, other_corner);
+                    }
+                }
+                *n = normalized_or_zero(sum);
+                if n.norm() == 0.0 {
+                    *n = plane_normal(crosses[m]);
+                }
+            }
+            generated.push(face_norms);
+        }
+        generated
+    }
+
+    /// The tangent and handedness at every corner, like [MikkTSpace] does it:
+    /// the tangents of the faces around a vertex are averaged weighted by their
+    /// corner angles, only between faces sharing the position, uv, normal and
+    /// handedness, and then made orthogonal to the normal.
+    ///
+    /// [MikkTSpace]: http://www.mikktspace.com/
+    fn compute_tangents(&self) -> Vec<(Vec3f, f64)> {
+        // the tangent and handedness of each face, with the tangent
+        // orthogonal to the corner's normal, weighted by the corner's angle
+        let mut corners = Vec::with_capacity(self.faces.len() * 3);
+        for f in 0..self.faces.len() {
+            let (p0, p1, p2) = (self.fvert(f, 0), self.fvert(f, 1), self.fvert(f, 2));
+            let (t0, t1, t2) = (self.fuv(f, 0), self.fuv(f, 1), self.fuv(f, 2));
+            let (e1, e2) = (p1 - p0, p2 - p0);
+            let (du1, dv1, du2, dv2) = (t1.x - t0.x, t1.y - t0.y, t2.x - t0.x, t2.y - t0.y);
+            // the determinant's sign says if the uvs are mirrored,
+            // the tangent doesn't need the division by it, only its sign
+            let det = du1 * dv2 - du2 * dv1;
+            let sign = if det < 0.0 { -1.0 } else { 1.0 };
+            let tangent = (e1 * dv2 - e2 * dv1) * sign;
+            let bitangent = (e2 * du1 - e1 * du2) * sign;
+            for v in 0..3 {
+                let n = self.fnorm(f, v);
+                let t = normalized_or_zero(tangent - n * (n * tangent));
+                // degenerate uvs give no direction, and shouldn't
+                // pull on the tangents of their neighbors
+                let weight = if det == 0.0 || t.norm() == 0.0 {
+                    0.0
+                } else {
+                    corner_angle(
+                        self.fvert(f, v),
+                        self.fvert(f, (v + 1) % 3),
+                        self.fvert(f, (v + 2) % 3),
+                    )
+                };
+                // the bitangent decides the handedness, mirrored faces don't share
+                let handedness = if n.cross(t) * bitangent < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                corners.push((t * weight, handedness));
+            }
+        }
+
+        let mut sums: HashMap<_, Vec3f> = HashMap::new();
+        let key = |f: usize, v: usize, handedness: f64| {
+            let face = &self.faces[f];
+            (
+                face.verts[v],
+                face.uvs.map(|uvs| uvs[v]),
+                face.norms.map(|norms| norms[v]),
+                handedness > 0.0,
+            )
+        };
+        for (i, &(t, handedness)) in corners.iter().enumerate() {
+            let sum = sums
+                .entry(key(i / 3, i % 3, handedness))
+                .or_insert_with(Vec3f::zero);
+            *sum = *sum + t;
+        }
+        corners
+            .iter()
+            .enumerate()
+            .map(|(i, &(_, handedness))| {
+                let n = self.fnorm(i / 3, i % 3);
+                let t = sums[&key(i / 3, i % 3, handedness)];
+                let t = normalized_or_zero(t - n * (n * t));
+                if t.norm() > 0.0 {
+                    (t, handedness)
+                } else {
+                    (perpendicular(n), 1.0)
+                }
+            })
+            .collect()
+    }
+
+    /// Transform the model by the 4x4 matrix, its vertices as points and its
+    /// normals by the inverse transpose, so they stay orthogonal to the surface
+    /// when it is scaled unevenly. Matrices that mirror the model turn its faces
+    /// around, so they keep facing out. Only meant for affine transforms,
+    /// normals ignore the bottom row.
+    pub fn transform(&mut self, m: &Matrix) {
+        assert!(m.rows == 4 && m.cols == 4, "transforms are 4x4");
+        for v in &mut self.verts {
+            *v = Vec3f::from_m(&(m * &Matrix::from_v(*v)));
+        }
+
+        // the cofactors are the inverse transpose times the determinant,
+        // the normals are normalized anyway, but must not flip
+        let at = |r: usize, c: usize| m.get(r % 3, c % 3);
+        let mut cofactors = [[0.0; 3]; 3];
+        for (r, row) in cofactors.iter_mut().enumerate() {
+            for (c, cofactor) in row.iter_mut().enumerate() {
+                *cofactor =
+                    at(r + 1, c + 1) * at(r + 2, c + 2) - at(r + 1, c + 2) * at(r + 2, c + 1);
+            }
+        }
+        let determinant: f64 = (0..3).map(|c| m.get(0, c) * cofactors[0][c]).sum();
+        let sign = if determinant < 0.0 { -1.0 } else { 1.0 };
+        for n in &mut self.norms {
+            let row = |r: usize| Vec3f::new(cofactors[r][0], cofactors[r][1], cofactors[r][2]) * *n;
+            *n = normalized_or_zero(Vec3f::new(row(0), row(1), row(2)) * sign);
+        }
+
+        if determinant < 0.0 {
+            for face in &mut self.faces {
+                face.verts = Vec3::new(face.verts.x, face.verts.z, face.verts.y);
+                face.uvs = face.uvs.map(|uvs| Vec3::new(uvs.x, uvs.z, uvs.y));
+                face.norms = face.norms.map(|norms| Vec3::new(norms.x, norms.z, norms.y));
             }
         }
-        Model {
-            verts: obj_verts,
-            uvs: obj_uvs,
-            norms: obj_norms,
-            faces: obj_faces,
+        self.tangents = self.compute_tangents();
+    }
+
+    /// Move the model's center to the origin and scale it evenly to fit
+    /// [-1, 1] in every axis, see `Aabb::to_unit`.
+    pub fn center_and_scale_to_unit(&mut self) {
+        if let Some(bounds) = self.bounds() {
+            self.transform(&bounds.to_unit());
         }
     }
 
+    /// the smallest box holding all the vertices, or `None` if there are none
+    pub fn bounds(&self) -> Option<Aabb> {
+        Aabb::from_points(self.verts.iter().copied())
+    }
+
     /// get vertex by index
     pub fn vert(&self, i: usize) -> Vec3f {
         self.verts[i]
@@ -118,7 +622,7 @@ impl Model {
         &self.faces[i]
     }
 
-    pub fn faces(&self) -> std::slice::Iter<Face> {
+    pub fn faces(&self) -> std::slice::Iter<'_, Face> {
         self.faces.iter()
     }
 
@@ -127,9 +631,12 @@ impl Model {
         self.uvs[i]
     }
 
-    /// get the `v`th uv coord of the face `f`
+    /// get the `v`th uv coord of the face `f`, or 0, 0 if the face has none
     pub fn fuv(&self, f: usize, v: usize) -> Vec3f {
-        self.uvs[self.faces[f].uvs[v]]
+        match self.faces[f].uvs {
+            Some(uvs) => self.uvs[uvs[v]],
+            None => Vec3f::zero(),
+        }
     }
 
     /// get a normal by index
@@ -137,39 +644,899 @@ impl Model {
         self.norms[i]
     }
 
-    /// get the `v`th normal of the face `f`
+    /// get the `v`th normal of the face `f`,
+    /// or the normal of the face's plane if the face has none
     pub fn fnorm(&self, f: usize, v: usize) -> Vec3f {
-        self.norms[self.faces[f].norms[v]]
-    }
-}
-
-fn parse_triplet<T>(tokens: &mut dyn Iterator<Item = &str>) -> (T, T, T)
-where
-    T: std::str::FromStr,
-    <T as std::str::FromStr>::Err: std::fmt::Debug,
-{
-    (parse_next(tokens), parse_next(tokens), parse_next(tokens))
-}
-
-fn parse_next<T: FromStr>(tokens: &mut dyn Iterator<Item = &str>) -> T
-where
-    T: std::str::FromStr,
-    <T as std::str::FromStr>::Err: std::fmt::Debug,
-{
-    tokens
-        .next()
-        .expect("not enough tokens")
-        .parse()
-        .expect("cannot parse token")
-}
-
-fn parse_next_or<T: FromStr>(tokens: &mut dyn Iterator<Item = &str>, default: T) -> T
-where
-    T: std::str::FromStr,
-    <T as std::str::FromStr>::Err: std::fmt::Debug,
-{
-    tokens
-        .next()
-        .map(|t| t.parse().expect("cannot parse token"))
-        .unwrap_or(default)
+        match self.faces[f].norms {
+            Some(norms) => self.norms[norms[v]],
+            None => {
+                let (a, b, c) = (self.fvert(f, 0), self.fvert(f, 1), self.fvert(f, 2));
+                plane_normal((b - a).cross(c - a))
+            }
+        }
+    }
+
+    /// the parts named by `o` lines, in the order they are in the file
+    pub fn objects(&self) -> &[SubMesh] {
+        &self.objects
+    }
+
+    /// the parts named by `g` lines, in the order they are in the file
+    pub fn groups(&self) -> &[SubMesh] {
+        &self.groups
+    }
+
+    /// do the vertices have colors?
+    pub fn has_colors(&self) -> bool {
+        !self.colors.is_empty()
+    }
+
+    /// get the color of the `v`th vertex of the face `f`, in sRGB,
+    /// or `None` if the vertices have no colors
+    pub fn fcolor(&self, f: usize, v: usize) -> Option<Rgba> {
+        self.colors.get(self.faces[f].verts[v]).copied()
+    }
+
+    /// Give the model the materials its faces refer to.
+    pub(crate) fn set_materials(&mut self, materials: Vec<Material>) {
+        self.materials = materials;
+    }
+
+    /// Give the vertices colors, one for each vertex.
+    pub(crate) fn set_colors(&mut self, colors: Vec<Rgba>) {
+        assert_eq!(colors.len(), self.verts.len(), "one color per vertex");
+        self.colors = colors;
+    }
+
+    /// the material libraries `mtllib` named that could not be opened,
+    /// their materials look like `Material::new`
+    pub fn missing_material_libraries(&self) -> &[String] {
+        &self.missing_material_libraries
+    }
+
+    /// all the materials faces can have, faces refer to them by index
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    /// get the material of the face `f`, if it has one
+    pub fn fmaterial(&self, f: usize) -> Option<&Material> {
+        self.faces[f].material.map(|m| &self.materials[m])
+    }
+
+    /// get the tangent of the `v`th vertex of the face `f`, pointing where u grows,
+    /// and its handedness, 1.0 or -1.0 for mirrored uvs, see `fbitangent`.
+    /// Faces without uvs or with degenerate uvs get some tangent orthogonal to the normal.
+    pub fn ftangent(&self, f: usize, v: usize) -> (Vec3f, f64) {
+        self.tangents[f * 3 + v]
+    }
+
+    /// get the bitangent of the `v`th vertex of the face `f`, pointing where v grows,
+    /// the cross product of the normal and the tangent, flipped by the handedness
+    pub fn fbitangent(&self, f: usize, v: usize) -> Vec3f {
+        let (tangent, handedness) = self.ftangent(f, v);
+        self.fnorm(f, v).cross(tangent) * handedness
+    }
+}
+
+/// How to weigh the faces around a vertex when averaging their normals.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NormalWeighting {
+    /// bigger faces count more
+    Area,
+    /// faces count by the angle of their corner at the vertex,
+    /// so how a surface is split into triangles doesn't matter
+    Angle,
+}
+
+/// How to generate normals, see `Model::generate_normals`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NormalMode {
+    /// every face gets the normal of its plane, for a faceted look
+    Flat,
+    /// faces sharing a vertex average their normals there, unless the angle
+    /// between them is bigger than `crease_angle` (in radians), or they are
+    /// in different smoothing groups, which keeps hard edges hard
+    Smooth {
+        weighting: NormalWeighting,
+        crease_angle: f64,
+    },
+}
+
+impl Default for NormalMode {
+    /// Smooth, angle weighted normals, with a crease angle of 60 degrees.
+    fn default() -> Self {
+        NormalMode::Smooth {
+            weighting: NormalWeighting::Angle,
+            crease_angle: FRAC_PI_3,
+        }
+    }
+}
+
+/// The angle at the corner `at` of the triangle `at`, `b`, `c`, in radians.
+fn corner_angle(at: Vec3f, b: Vec3f, c: Vec3f) -> f64 {
+    let (a, b) = (b - at, c - at);
+    // unlike acos, fine with zero length edges
+    a.cross(b).norm().atan2(a * b)
+}
+
+/// The normal of a face's plane from the `cross` product of its edges,
+/// or the z axis for faces without an area, which have no plane.
+fn plane_normal(cross: Vec3f) -> Vec3f {
+    match normalized_or_zero(cross) {
+        n if n.norm() > 0.0 => n,
+        _ => Vec3f::new(0.0, 0.0, 1.0),
+    }
+}
+
+/// Some unit vector orthogonal to `n`, or the x axis if `n` is all zeros.
+fn perpendicular(n: Vec3f) -> Vec3f {
+    // cross with the axis the normal is least along of
+    let axis = if n.x.abs() <= n.y.abs() && n.x.abs() <= n.z.abs() {
+        Vec3f::new(1.0, 0.0, 0.0)
+    } else if n.y.abs() <= n.z.abs() {
+        Vec3f::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3f::new(0.0, 0.0, 1.0)
+    };
+    match normalized_or_zero(n.cross(axis)) {
+        t if t.norm() > 0.0 => t,
+        _ => Vec3f::new(1.0, 0.0, 0.0),
+    }
+}
+
+/// The vector scaled to length 1, or left as it is if it is all zeros.
+fn normalized_or_zero(v: Vec3f) -> Vec3f {
+    let norm = v.norm();
+    if norm > 0.0 {
+        v * (1.0 / norm)
+    } else {
+        v
+    }
+}
+
+/// Directives we know about, but don't do anything with (yet): those for free-form
+/// curves and surfaces, points and lines, merging groups, display and rendering,
+/// and the general statements that run other files and commands.
+const IGNORED_DIRECTIVES: [&str; 31] = [
+    "vp",
+    "cstype",
+    "deg",
+    "bmat",
+    "step",
+    "curv",
+    "curv2",
+    "surf",
+    "parm",
+    "trim",
+    "hole",
+    "scrv",
+    "sp",
+    "end",
+    "con",
+    "bzp",
+    "p",
+    "l",
+    "mg",
+    "bevel",
+    "c_interp",
+    "d_interp",
+    "lod",
+    "usemap",
+    "maplib",
+    "shadow_obj",
+    "trace_obj",
+    "ctech",
+    "stech",
+    "call",
+    "csh",
+];
+
+/// Write a `directive` line naming the parts starting at the face `f`, if any do.
+fn write_part_starts<W: Write>(
+    writer: &mut BufWriter<W>,
+    directive: &str,
+    parts: &[SubMesh],
+    f: usize,
+) -> io::Result<()> {
+    let names: Vec<&str> = parts
+        .iter()
+        .filter(|part| part.faces.start == f)
+        .map(|part| part.name.as_str())
+        .collect();
+    if names.is_empty() {
+        return Ok(());
+    }
+    // parts without faces are dropped, so only groups can start together
+    writeln!(writer, "{} {}", directive, names.join(" "))
+}
+
+/// The parts of a model being read, each ending where the next one starts.
+#[derive(Default)]
+struct SubMeshes {
+    parts: Vec<SubMesh>,
+    names: Vec<String>,
+    start: usize,
+}
+
+impl SubMeshes {
+    /// End the current parts, and start parts with the given names at the face `at`.
+    fn start(&mut self, names: Vec<String>, at: usize) {
+        self.end(at);
+        self.names = names;
+        self.start = at;
+    }
+
+    /// End the current parts at the face `at`, parts without faces are dropped.
+    fn end(&mut self, at: usize) {
+        for name in self.names.drain(..) {
+            if at > self.start {
+                self.parts.push(SubMesh {
+                    name,
+                    faces: self.start..at,
+                });
+            }
+        }
+    }
+
+    /// All the parts, the last one ending at the face `at`.
+    fn finish(mut self, at: usize) -> Vec<SubMesh> {
+        self.end(at);
+        self.parts
+    }
+}
+
+/// The materials of a model being read, by name.
+/// `usemtl` may name a material before, or without, a library defining it,
+/// so it gets a default one that is replaced if a library defines it later.
+#[derive(Default)]
+struct MaterialTable {
+    materials: Vec<Material>,
+    indices: HashMap<String, usize>,
+}
+
+impl MaterialTable {
+    /// Add the material, replacing any with the same name.
+    fn define(&mut self, material: Material) {
+        match self.indices.get(&material.name) {
+            Some(&i) => self.materials[i] = material,
+            None => {
+                self.indices
+                    .insert(material.name.clone(), self.materials.len());
+                self.materials.push(material);
+            }
+        }
+    }
+
+    /// The index of the material with the given name.
+    fn index(&mut self, name: &str) -> usize {
+        if !self.indices.contains_key(name) {
+            self.define(Material::new(name));
+        }
+        self.indices[name]
+    }
+}
+
+/// Why an .obj file could not be parsed.
+/// Lines and columns count from 1, columns are in bytes.
+#[derive(Debug)]
+pub enum ObjError {
+    /// the data could not be read at all
+    Io(io::Error),
+    /// a coordinate is not a valid number
+    BadFloat {
+        line: usize,
+        column: usize,
+        token: String,
+    },
+    /// a vertex, uv, normal, or smoothing group index is not a valid number
+    BadIndex {
+        line: usize,
+        column: usize,
+        token: String,
+    },
+    /// a coordinate or an index is missing
+    MissingValue { line: usize, column: usize },
+    /// an index refers to data that has not been defined
+    IndexOutOfRange {
+        line: usize,
+        column: usize,
+        token: String,
+        len: usize,
+    },
+    /// the line starts with something we cannot make sense of
+    UnsupportedDirective {
+        line: usize,
+        column: usize,
+        token: String,
+    },
+    /// a face vertex has uv coords or a normal when the first vertex
+    /// of the face does not, or the other way around
+    InconsistentFace {
+        line: usize,
+        column: usize,
+        token: String,
+    },
+    /// a material library could not be read after opening it, or is malformed
+    BadLibrary {
+        line: usize,
+        column: usize,
+        name: String,
+        error: Box<ObjError>,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io(e) => write!(f, "cannot read OBJ data: {}", e),
+            ObjError::BadFloat {
+                line,
+                column,
+                token,
+            } => write!(f, "{}:{}: bad number {:?}", line, column, token),
+            ObjError::BadIndex {
+                line,
+                column,
+                token,
+            } => write!(f, "{}:{}: bad index {:?}", line, column, token),
+            ObjError::MissingValue { line, column } => {
+                write!(f, "{}:{}: missing value", line, column)
+            }
+            ObjError::IndexOutOfRange {
+                line,
+                column,
+                token,
+                len,
+            } => write!(
+                f,
+                "{}:{}: index {:?} out of range, there are {} elements",
+                line, column, token, len
+            ),
+            ObjError::UnsupportedDirective {
+                line,
+                column,
+                token,
+            } => write!(f, "{}:{}: unsupported directive {:?}", line, column, token),
+            ObjError::InconsistentFace {
+                line,
+                column,
+                token,
+            } => write!(
+                f,
+                "{}:{}: face vertex {:?} does not match the first one",
+                line, column, token
+            ),
+            ObjError::BadLibrary {
+                line,
+                column,
+                name,
+                error,
+            } => write!(
+                f,
+                "{}:{}: in material library {:?}: {}",
+                line, column, name, error
+            ),
+        }
+    }
+}
+
+impl error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ObjError::Io(e) => Some(e),
+            ObjError::BadLibrary { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// A line of .obj or .mtl data being tokenized, remembering where it is for error reporting.
+pub(crate) struct ObjLine<'a> {
+    number: usize,
+    text: &'a str,
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> ObjLine<'a> {
+    pub(crate) fn new(number: usize, text: &'a str) -> ObjLine<'a> {
+        ObjLine {
+            number,
+            text,
+            tokens: text.split_whitespace(),
+        }
+    }
+
+    pub(crate) fn next(&mut self) -> Option<&'a str> {
+        self.tokens.next()
+    }
+
+    pub(crate) fn peek(&self) -> Option<&'a str> {
+        self.tokens.clone().next()
+    }
+
+    /// the rest of the line from the next token on, for names with spaces
+    pub(crate) fn rest(&self) -> Option<&'a str> {
+        let next = self.peek()?;
+        Some(self.text[self.column(next) - 1..].trim_end())
+    }
+
+    /// where in the line the given token (which must be a slice of it) is
+    pub(crate) fn column(&self, token: &str) -> usize {
+        token.as_ptr() as usize - self.text.as_ptr() as usize + 1
+    }
+
+    pub(crate) fn missing(&self) -> ObjError {
+        ObjError::MissingValue {
+            line: self.number,
+            column: self.text.trim_end().len() + 1,
+        }
+    }
+
+    fn parse_float(&self, token: &str) -> Result<f64, ObjError> {
+        token.parse().map_err(|_| ObjError::BadFloat {
+            line: self.number,
+            column: self.column(token),
+            token: token.to_owned(),
+        })
+    }
+
+    pub(crate) fn float(&mut self) -> Result<f64, ObjError> {
+        match self.next() {
+            Some(token) => self.parse_float(token),
+            None => Err(self.missing()),
+        }
+    }
+
+    pub(crate) fn float_or(&mut self, default: f64) -> Result<f64, ObjError> {
+        match self.next() {
+            Some(token) => self.parse_float(token),
+            None => Ok(default),
+        }
+    }
+
+    /// Parse the rest of the line as `v`, `v/vt`, `v//vn`, or `v/vt/vn`
+    /// face tokens, at least three, all in the same form.
+    fn vertex_refs(&mut self, counts: (usize, usize, usize)) -> Result<Vec<VertexRef>, ObjError> {
+        let mut refs = Vec::new();
+        while let Some(token) = self.next() {
+            let vertex_ref = self.vertex_ref(token, counts)?;
+            if let Some(first) = refs.first() {
+                let (_, uv, n): VertexRef = *first;
+                if uv.is_some() != vertex_ref.1.is_some() || n.is_some() != vertex_ref.2.is_some() {
+                    return Err(ObjError::InconsistentFace {
+                        line: self.number,
+                        column: self.column(token),
+                        token: token.to_owned(),
+                    });
+                }
+            }
+            refs.push(vertex_ref);
+        }
+        if refs.len() < 3 {
+            return Err(self.missing());
+        }
+        Ok(refs)
+    }
+
+    /// Parse a face token into zero based indices, checking them against
+    /// how many verts, uvs, and normals there are.
+    fn vertex_ref(
+        &self,
+        token: &str,
+        counts: (usize, usize, usize),
+    ) -> Result<VertexRef, ObjError> {
+        let mut parts = token.split('/');
+        let v = self.index(token, parts.next(), counts.0)?;
+        let uv = match parts.next() {
+            Some("") | None => None,
+            part => Some(self.index(token, part, counts.1)?),
+        };
+        let n = match parts.next() {
+            None => None,
+            part => Some(self.index(token, part, counts.2)?),
+        };
+        if parts.next().is_some() {
+            return Err(ObjError::BadIndex {
+                line: self.number,
+                column: self.column(token),
+                token: token.to_owned(),
+            });
+        }
+        Ok((v, uv, n))
+    }
+
+    fn index(&self, token: &str, part: Option<&str>, len: usize) -> Result<usize, ObjError> {
+        let column = self.column(token);
+        let part = match part {
+            Some(p) if !p.is_empty() => p,
+            _ => {
+                return Err(ObjError::MissingValue {
+                    line: self.number,
+                    column,
+                })
+            }
+        };
+        let i: i64 = part.parse().map_err(|_| ObjError::BadIndex {
+            line: self.number,
+            column,
+            token: token.to_owned(),
+        })?;
+        // in wavefront obj indices start at 1, not 0,
+        // and negative ones count back from the last element defined so far
+        let i = if i < 0 { len as i64 + i } else { i - 1 };
+        if i < 0 || i >= len as i64 {
+            return Err(ObjError::IndexOutOfRange {
+                line: self.number,
+                column,
+                token: token.to_owned(),
+                len,
+            });
+        }
+        Ok(i as usize)
+    }
+}
+
+/// Zero based indices of a face vertex's position, and maybe uv coords and normal.
+type VertexRef = (usize, Option<usize>, Option<usize>);
+
+/// Split a polygon into triangles by [ear clipping], so concave polygons work too.
+/// Returns indices into `points`, each triangle wound the same way as the polygon.
+///
+/// [ear clipping]: https://en.wikipedia.org/wiki/Polygon_triangulation#Ear_clipping_method
+pub(crate) fn triangulate(points: &[Vec3f]) -> Vec<[usize; 3]> {
+    if points.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // flatten the polygon onto the axis plane it is closest to facing,
+    // with the normal from Newell's method, which works for concave polygons
+    let mut normal = Vec3f::zero();
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        normal.x += (a.y - b.y) * (a.z + b.z);
+        normal.y += (a.z - b.z) * (a.x + b.x);
+        normal.z += (a.x - b.x) * (a.y + b.y);
+    }
+    let (ax, ay, az) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+    let (flat, facing): (Vec<(f64, f64)>, f64) = if az >= ax && az >= ay {
+        (points.iter().map(|p| (p.x, p.y)).collect(), normal.z)
+    } else if ax >= ay {
+        (points.iter().map(|p| (p.y, p.z)).collect(), normal.x)
+    } else {
+        (points.iter().map(|p| (p.z, p.x)).collect(), normal.y)
+    };
+    // positive if a, b, c turn the same way as the polygon
+    let turn = |a: usize, b: usize, c: usize| {
+        let (a, b, c) = (flat[a], flat[b], flat[c]);
+        ((b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)) * facing.signum()
+    };
+
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            );
+            // an ear is convex, and has no other corner inside it
+            turn(a, b, c) > 0.0
+                && remaining
+                    .iter()
+                    .filter(|&&p| p != a && p != b && p != c)
+                    .all(|&p| turn(a, b, p) < 0.0 || turn(b, c, p) < 0.0 || turn(c, a, p) < 0.0)
+        });
+        match ear {
+            Some(i) => {
+                let (a, b, c) = (
+                    remaining[(i + n - 1) % n],
+                    remaining[i],
+                    remaining[(i + 1) % n],
+                );
+                triangles.push([a, b, c]);
+                remaining.remove(i);
+            }
+            // degenerate polygons have no ears, make a fan of what is left
+            None => break,
+        }
+    }
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const MTL: &str = "newmtl red\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\n";
+
+    fn read(obj: &str) -> Model {
+        Model::try_from_obj_with(obj.as_bytes(), |_| Ok(MTL.as_bytes())).unwrap()
+    }
+
+    fn write(model: &Model, precision: usize) -> String {
+        let mut writer = BufWriter::new(Vec::new());
+        model.write_obj(&mut writer, precision).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    fn round_trip(model: &Model) -> Model {
+        read(&write(model, 6))
+    }
+
+    fn assert_same_faces(a: &Model, b: &Model, tolerance: f64) {
+        assert_eq!(a.nfaces(), b.nfaces());
+        for f in 0..a.nfaces() {
+            for v in 0..3 {
+                let d = a.fvert(f, v) - b.fvert(f, v);
+                assert!(d.norm() <= tolerance, "face {} vertex {} moved", f, v);
+            }
+        }
+    }
+
+    const SQUARES: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\n";
+
+    #[test]
+    fn keeps_objects_and_groups() {
+        let model = read(&format!(
+            "{}o first\ng a b\nf 1 2 3\nf 1 3 4\ng\nf 1 2 5\no second\ng c\nf 2 6 5\n",
+            SQUARES
+        ));
+        let again = round_trip(&model);
+        assert_eq!(again.objects(), model.objects());
+        assert_eq!(again.groups(), model.groups());
+        let names: Vec<&str> = model.groups().iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "default", "c"]);
+    }
+
+    #[test]
+    fn keeps_material_changes() {
+        let model = read(&format!(
+            "mtllib lib.mtl\n{}f 1 2 3\nusemtl red\nf 1 3 4\nusemtl blue\nf 1 2 5\n\
+             usemtl\nf 2 6 5\nusemtl red\nf 1 2 6\n",
+            SQUARES
+        ));
+        let names = |model: &Model| -> Vec<Option<String>> {
+            (0..model.nfaces())
+                .map(|f| model.fmaterial(f).map(|m| m.name.clone()))
+                .collect()
+        };
+        let expected = [None, Some("red"), Some("blue"), None, Some("red")];
+        let expected: Vec<_> = expected.iter().map(|n| n.map(str::to_owned)).collect();
+        assert_eq!(names(&model), expected);
+        let again = round_trip(&model);
+        assert_eq!(names(&again), expected);
+        assert_eq!(again.materials()[0].diffuse, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn skips_missing_libraries() {
+        let obj = format!("mtllib gone.mtl\n{}usemtl red\nf 1 2 3\n", SQUARES);
+        let model = Model::try_from_obj_with(obj.as_bytes(), |_| {
+            File::open("/nonexistent/gone.mtl").map(BufReader::new)
+        })
+        .unwrap();
+        assert_eq!(model.missing_material_libraries(), ["gone.mtl"]);
+        assert_eq!(model.fmaterial(0).unwrap(), &Material::new("red"));
+        // but it is still used
+        assert!(write(&model, 6).starts_with("mtllib gone.mtl\n"));
+    }
+
+    #[test]
+    fn malformed_libraries_are_an_error() {
+        let obj = format!("mtllib bad.mtl\n{}f 1 2 3\n", SQUARES);
+        let result = Model::try_from_obj_with(obj.as_bytes(), |_| Ok(&b"newmtl a\nKd x 0 0\n"[..]));
+        match result {
+            Err(ObjError::BadLibrary { name, error, .. }) => {
+                assert_eq!(name, "bad.mtl");
+                assert!(matches!(*error, ObjError::BadFloat { .. }), "{}", error);
+            }
+            _ => panic!("expected a BadLibrary error"),
+        }
+    }
+
+    #[test]
+    fn ignores_known_directives_only() {
+        let model = read(&format!(
+            "{}mg 1 0.5\nlod 2\nusemap wood\nshadow_obj shadow.obj\nl 1 2\nf 1 2 3\n",
+            SQUARES
+        ));
+        assert_eq!(model.nfaces(), 1);
+        let result = Model::try_from_obj_with(&b"v 0 0 0\nfoo 1\n"[..], |_| Ok(MTL.as_bytes()));
+        match result {
+            Err(ObjError::UnsupportedDirective { line, token, .. }) => {
+                assert_eq!((line, token.as_str()), (2, "foo"));
+            }
+            _ => panic!("expected an UnsupportedDirective error"),
+        }
+    }
+
+    #[test]
+    fn keeps_smoothing_groups() {
+        let model = read(&format!(
+            "{}f 1 2 3\ns 1\nf 1 3 4\ns off\nf 1 2 5\ns 7\nf 2 6 5\n",
+            SQUARES
+        ));
+        let groups =
+            |model: &Model| -> Vec<u32> { model.faces().map(|f| f.smoothing_group).collect() };
+        assert_eq!(groups(&model), [0, 1, 0, 7]);
+        assert_eq!(groups(&round_trip(&model)), [0, 1, 0, 7]);
+    }
+
+    #[test]
+    fn writes_polygons_and_negative_indices_as_triangles() {
+        let model = read(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             f -4/-4 -3/-3 -2/-2 -1/-1\n\
+             v 2 0 0\nv 3 0 0\nv 3 1 0\nv 2.5 2 0\nv 2 1 0\nf 5 6 7 8 9\n",
+        );
+        assert_eq!(model.nfaces(), 2 + 3);
+        let written = write(&model, 6);
+        assert!(!written.contains("-"), "indices are written positive");
+        let again = read(&written);
+        assert_same_faces(&model, &again, 0.0);
+        for v in 0..3 {
+            let d = again.fuv(0, v) - model.fuv(0, v);
+            assert_eq!(d.norm(), 0.0);
+        }
+        assert!(again.face(4).uvs.is_none());
+    }
+
+    #[test]
+    fn writes_with_precision() {
+        let model = read("v 0.123456789 -1.987654321 2\nv 1 0 0\nv 0 1 0\nf 1 2 3\n");
+        let written = write(&model, 3);
+        assert!(written.starts_with("v 0.123 -1.988 2.000\n"), "{}", written);
+        let again = read(&written);
+        assert_same_faces(&model, &again, 0.0005 * 3f64.sqrt());
+        // what was written at a precision is written the same again
+        assert_eq!(write(&again, 3), written);
+    }
+
+    // the faces wind counterclockwise seen from outside, split into triangles
+    const CUBE: &str = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+                        v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+                        f 1 4 3 2\nf 5 6 7 8\nf 1 5 8 4\nf 2 3 7 6\nf 1 2 6 5\nf 4 8 7 3\n";
+
+    fn assert_close(a: Vec3f, b: Vec3f) {
+        assert!((a - b).norm() < 1e-9, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn generates_flat_normals() {
+        let mut model = read(CUBE);
+        model.generate_normals(NormalMode::Flat);
+        assert_eq!(model.norms.len(), 6, "each side's normal is stored once");
+        for f in 0..model.nfaces() {
+            let centroid = (0..3).fold(Vec3f::zero(), |sum, v| sum + model.fvert(f, v));
+            // the corners share the coordinate of the side they are on, the others cancel out
+            let side = |c: f64| (c / 3.0).trunc();
+            let axis = Vec3f::new(side(centroid.x), side(centroid.y), side(centroid.z));
+            for v in 0..3 {
+                assert_close(model.fnorm(f, v), axis);
+            }
+        }
+    }
+
+    #[test]
+    fn generates_smooth_normals_within_the_crease_angle() {
+        let mut model = read(CUBE);
+        model.generate_normals(NormalMode::Flat);
+        let flat: Vec<Vec3f> = (0..model.nfaces() * 3)
+            .map(|i| model.fnorm(i / 3, i % 3))
+            .collect();
+        // the sides meet at right angles, which the default keeps as hard edges
+        model.generate_normals(NormalMode::default());
+        for (i, &n) in flat.iter().enumerate() {
+            assert_close(model.fnorm(i / 3, i % 3), n);
+        }
+        // without a crease every corner points away from the center, as each
+        // side's corner angles add up to a right angle however it is split
+        model.generate_normals(NormalMode::Smooth {
+            weighting: NormalWeighting::Angle,
+            crease_angle: PI,
+        });
+        assert_eq!(model.norms.len(), 8);
+        for f in 0..model.nfaces() {
+            for v in 0..3 {
+                assert_close(model.fnorm(f, v), model.fvert(f, v) * (1.0 / 3f64.sqrt()));
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_faces_get_some_normal() {
+        let z = Vec3f::new(0.0, 0.0, 1.0);
+        let obj = "v 0 0 0\nv 1 0 0\nv 2 0 0\nv 0 1 0\nf 1 2 3\nf 1 2 4\nf 1 1 4\n";
+        for &mode in &[NormalMode::Flat, NormalMode::default()] {
+            let mut model = read(obj);
+            model.generate_normals(mode);
+            for f in 0..model.nfaces() {
+                for v in 0..3 {
+                    assert_close(model.fnorm(f, v), z);
+                    let (tangent, handedness) = model.ftangent(f, v);
+                    assert!(tangent.norm().is_finite() && handedness.is_finite());
+                }
+            }
+        }
+        // faces without normals give those of their plane, if they have one
+        let mut model = read(obj);
+        model.faces[0].norms = None;
+        assert_close(model.fnorm(0, 0), z);
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_the_handedness() {
+        // the same triangle twice, the second with its texture mirrored along u
+        let mut model = read(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 2 0 0\nv 3 0 0\nv 2 1 0\n\
+             vt 0 0\nvt 1 0\nvt 0 1\nvt 1 1\n\
+             f 1/1 2/2 3/3\nf 4/2 5/1 6/4\n",
+        );
+        model.generate_normals(NormalMode::default());
+        let x = Vec3f::new(1.0, 0.0, 0.0);
+        let y = Vec3f::new(0.0, 1.0, 0.0);
+        for &(f, u, handedness) in &[(0, x, 1.0), (1, x * -1.0, -1.0)] {
+            for v in 0..3 {
+                let (tangent, h) = model.ftangent(f, v);
+                assert_close(tangent, u);
+                assert_eq!(h, handedness);
+                assert_eq!(tangent * model.fnorm(f, v), 0.0);
+                // either way the bitangent points where v grows
+                assert_close(model.fbitangent(f, v), y);
+            }
+        }
+    }
+
+    fn scale_x(by: f64) -> Matrix {
+        let mut m = Matrix::identity(4);
+        m.put(0, 0, by);
+        m
+    }
+
+    #[test]
+    fn transforms_normals_by_the_inverse_transpose() {
+        // a slope along x + y = 1, which stretching x turns to x / 2 + y = 1
+        let mut model = read("v 1 0 0\nv 0 1 0\nv 1 0 1\nf 1 2 3\n");
+        model.generate_normals(NormalMode::Flat);
+        assert_close(
+            model.fnorm(0, 0),
+            Vec3f::new(1.0, 1.0, 0.0) * (1.0 / 2f64.sqrt()),
+        );
+        model.transform(&scale_x(2.0));
+        assert_close(model.fvert(0, 0), Vec3f::new(2.0, 0.0, 0.0));
+        for v in 0..3 {
+            assert_close(
+                model.fnorm(0, v),
+                Vec3f::new(1.0, 2.0, 0.0) * (1.0 / 5f64.sqrt()),
+            );
+        }
+    }
+
+    #[test]
+    fn mirroring_flips_the_winding() {
+        let mut model = read(CUBE);
+        model.generate_normals(NormalMode::Flat);
+        model.transform(&scale_x(-1.0));
+        for f in 0..model.nfaces() {
+            let (a, b, c) = (model.fvert(f, 0), model.fvert(f, 1), model.fvert(f, 2));
+            let centroid = a + b + c;
+            let cross = (b - a).cross(c - a);
+            for v in 0..3 {
+                // still counterclockwise seen from outside, with the normals outside
+                let n = model.fnorm(f, v);
+                assert!(
+                    cross * n > 0.0 && centroid * n > 0.0,
+                    "face {} turned inside",
+                    f
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,142 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Materials, and reading the [.mtl files] that go with Wavefront .obj files.
+//!
+//! [.mtl files]: https://en.wikipedia.org/wiki/Wavefront_.obj_file#Material_template_library
+//!
+
+use std::io::BufRead;
+use std::path::PathBuf;
+
+use crate::model::ObjError;
+use crate::model::ObjLine;
+
+/// How a surface looks, the colors multiply what the textures say.
+/// Texture paths are as written in the .mtl file, unless the model
+/// was read with `Model::from_obj_file`, which makes them relative to
+/// the working directory.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Material {
+    pub name: String,
+    /// `Kd`, the diffuse color in linear light, white if the file has none
+    pub diffuse: [f64; 3],
+    /// `Ks`, the color of highlights, black if the file has none
+    pub specular: [f64; 3],
+    /// `Ns`, how sharp highlights are, from 0 to 1000
+    pub shininess: f64,
+    /// `d`, or 1 - `Tr`, how opaque the material is
+    pub dissolve: f64,
+    /// `illum`, the illumination model, which renderers may pick features by
+    pub illum: u32,
+    /// `map_Kd`, the diffuse texture
+    pub diffuse_map: Option<PathBuf>,
+    /// `map_Ks`, the specular color texture
+    pub specular_map: Option<PathBuf>,
+    /// `map_Bump` or `bump`, the bump or normal map
+    pub bump_map: Option<PathBuf>,
+    /// `map_d`, the opacity texture, its values are not colors
+    pub dissolve_map: Option<PathBuf>,
+}
+
+impl Material {
+    /// A white, opaque material without textures.
+    pub fn new(name: &str) -> Material {
+        Material {
+            name: name.to_owned(),
+            diffuse: [1.0, 1.0, 1.0],
+            specular: [0.0, 0.0, 0.0],
+            shininess: 0.0,
+            dissolve: 1.0,
+            illum: 2,
+            diffuse_map: None,
+            specular_map: None,
+            bump_map: None,
+            dissolve_map: None,
+        }
+    }
+
+    /// Parse the given data as a .mtl file, with any number of materials.
+    /// Statements we don't know about are skipped, there are too many
+    /// vendor extensions to treat them as errors like `Model::try_from_obj` does.
+    pub fn try_from_mtl<R: BufRead>(mtl_data: R) -> Result<Vec<Material>, ObjError> {
+        let mut materials: Vec<Material> = Vec::new();
+        for (i, line) in mtl_data.lines().enumerate() {
+            let text = line.map_err(ObjError::Io)?;
+            let line = &mut ObjLine::new(i + 1, &text);
+            let directive = match line.next() {
+                Some("newmtl") => {
+                    let name = line.rest().ok_or_else(|| line.missing())?;
+                    materials.push(Material::new(name));
+                    continue;
+                }
+                Some(directive) => directive,
+                None => continue,
+            };
+            // statements before the first newmtl have nothing to apply to
+            let material = match materials.last_mut() {
+                Some(material) => material,
+                None => continue,
+            };
+            match directive {
+                "Kd" => material.diffuse = line.color()?,
+                "Ks" => material.specular = line.color()?,
+                "Ns" => material.shininess = line.float()?,
+                "d" => material.dissolve = line.float()?,
+                "Tr" => material.dissolve = 1.0 - line.float()?,
+                "illum" => material.illum = line.float()? as u32,
+                "map_Kd" => material.diffuse_map = Some(line.map_path()?),
+                "map_Ks" => material.specular_map = Some(line.map_path()?),
+                "map_Bump" | "map_bump" | "bump" => material.bump_map = Some(line.map_path()?),
+                "map_d" => material.dissolve_map = Some(line.map_path()?),
+                _ => continue,
+            }
+        }
+        Ok(materials)
+    }
+}
+
+impl ObjLine<'_> {
+    /// Parse an `r g b` color, where `g` and `b` default to `r`.
+    fn color(&mut self) -> Result<[f64; 3], ObjError> {
+        let r = self.float()?;
+        Ok([r, self.float_or(r)?, self.float_or(r)?])
+    }
+
+    /// Parse the rest of a texture map statement, skipping the options
+    /// before the file name, which may have spaces.
+    fn map_path(&mut self) -> Result<PathBuf, ObjError> {
+        loop {
+            let rest = self.rest().ok_or_else(|| self.missing())?;
+            if !rest.starts_with('-') {
+                return Ok(PathBuf::from(rest));
+            }
+            let option = self.next().unwrap_or_default();
+            // options take one value, except the vectors, which take one to three
+            let values = match option {
+                "-o" | "-s" | "-t" => 3,
+                "-mm" => 2,
+                _ => 1,
+            };
+            for i in 0..values {
+                let is_number = self.peek().map_or(false, |t| t.parse::<f64>().is_ok());
+                if i > 0 && !is_number {
+                    break;
+                }
+                self.next().ok_or_else(|| self.missing())?;
+            }
+        }
+    }
+}
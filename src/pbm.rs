@@ -13,26 +13,534 @@
 // limitations under the License.
 
 //!
-//! Netpbm support,
-//! because also implementing _creating_ TGA files seemed like a bother.
+//! [Netpbm] support, the simplest format to write images in, and to read them back.
+//! See `Image::write_tga` for when something more widely supported is needed.
+//!
+//! [Netpbm]: http://netpbm.sourceforge.net/doc/
 //!
 
+use std::error;
+use std::fmt;
+use std::io;
 use std::io::BufWriter;
+use std::io::Read;
 use std::io::Write;
 
+use crate::image::ColorSpace;
 use crate::image::Image;
+use crate::image::Pixel;
+use crate::image::Rgba;
+
+/// The Netpbm formats we can write.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PnmFormat {
+    /// P3, an ASCII PixMap, easy to diff
+    PixMapAscii,
+    /// P5, a binary GrayMap, for things like depth maps
+    GrayMap,
+    /// P6, a binary PixMap
+    PixMap,
+    /// P7, a PAM image with an alpha channel
+    PamRgbAlpha,
+}
 
-impl Image {
+impl<P: Pixel> Image<P> {
     /// Save the bitmap data as a Netpbm image (P6, a binary PixMap).
     pub fn write<W: Write>(&self, writer: &mut BufWriter<W>) {
-        let header = format!("P6 {} {} {}\n", self.width, self.height, 255);
-        writer
-            .write_all(header.as_bytes())
-            .expect("unable to write image header");
-
-        // TODO I don't think this handles flipped images properly.
-        for p in &self.pixels {
-            writer.write_all(p).expect("unable to write pixel data");
+        self.write_pnm(writer, PnmFormat::PixMap)
+            .expect("unable to write image");
+    }
+
+    /// Save the bitmap data as a Netpbm image in the given format.
+    /// The top row is always written first, flipped or not.
+    /// Only `PamRgbAlpha` keeps the alpha of images that have it.
+    pub fn write_pnm<W: Write>(
+        &self,
+        writer: &mut BufWriter<W>,
+        format: PnmFormat,
+    ) -> io::Result<()> {
+        let (width, height) = (self.width, self.height);
+        match format {
+            PnmFormat::PixMapAscii => {
+                writeln!(writer, "P3 {} {} {}", width, height, 255)?;
+                self.write_ascii(writer)
+            }
+            PnmFormat::GrayMap => {
+                writeln!(writer, "P5 {} {} {}", width, height, 255)?;
+                for p in &self.pixels {
+                    writer.write_all(&[luma(p.to_rgba())])?;
+                }
+                Ok(())
+            }
+            PnmFormat::PixMap => {
+                writeln!(writer, "P6 {} {} {}", width, height, 255)?;
+                for p in &self.pixels {
+                    writer.write_all(&p.to_rgba()[..3])?;
+                }
+                Ok(())
+            }
+            PnmFormat::PamRgbAlpha => {
+                writeln!(writer, "P7")?;
+                writeln!(writer, "WIDTH {}", width)?;
+                writeln!(writer, "HEIGHT {}", height)?;
+                writeln!(writer, "DEPTH 4")?;
+                writeln!(writer, "MAXVAL 255")?;
+                writeln!(writer, "TUPLTYPE RGB_ALPHA")?;
+                writeln!(writer, "ENDHDR")?;
+                // pixels without alpha are written fully opaque
+                for p in &self.pixels {
+                    writer.write_all(&p.to_rgba())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the samples as decimal numbers, starting each row on a new line.
+    fn write_ascii<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        // lines should not be longer than 70 characters
+        const MAX_LINE: usize = 70;
+        for row in self.rows() {
+            let mut line = String::new();
+            for p in row {
+                let [r, g, b, _] = p.to_rgba();
+                for sample in &[r, g, b] {
+                    let sample = sample.to_string();
+                    if !line.is_empty() && line.len() + 1 + sample.len() > MAX_LINE {
+                        writeln!(writer, "{}", line)?;
+                        line.clear();
+                    }
+                    if !line.is_empty() {
+                        line.push(' ');
+                    }
+                    line.push_str(&sample);
+                }
+            }
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    /// Parse the given Netpbm data into a bitmap image.
+    ///
+    /// Reads bitmaps, graymaps, and pixmaps, both ASCII and binary (P1 to P6),
+    /// and PAM images (P7). Samples with a maxval other than 255 are scaled,
+    /// and may be 16 bits. Alpha is kept if the image has an alpha channel,
+    /// and dropped otherwise. The image is tagged as sRGB.
+    pub fn from_pnm<R: Read>(data: &mut R) -> Result<Image<P>, PnmError> {
+        let mut bytes = Vec::new();
+        data.read_to_end(&mut bytes).map_err(PnmError::Io)?;
+        let mut parser = PnmParser {
+            data: &bytes,
+            pos: 0,
+        };
+
+        let magic = parser.magic()?;
+        let header = match magic {
+            b'7' => parser.pam_header()?,
+            _ => parser.pnm_header(magic)?,
+        };
+        let samples = match magic {
+            b'1' => parser.ascii_bits(&header)?,
+            b'2' | b'3' => parser.ascii_samples(&header)?,
+            b'4' => parser.binary_bits(&header)?,
+            _ => parser.binary_samples(&header)?,
+        };
+
+        let pixels = samples
+            .chunks_exact(header.depth)
+            .map(|tuple| P::from_rgba(header.color(tuple)))
+            .collect();
+        Ok(Image {
+            width: header.width,
+            height: header.height,
+            flipped: false,
+            // Netpbm says BT.709, which is close enough
+            color_space: ColorSpace::Srgb,
+            pixels,
+        })
+    }
+}
+
+/// Why Netpbm data could not be decoded.
+#[derive(Debug)]
+pub enum PnmError {
+    /// the data could not be read
+    Io(io::Error),
+    /// the data does not start with P1 to P7
+    UnknownFormat,
+    /// a header field is missing or not a valid value
+    BadHeader(String),
+    /// the maxval is 0 or more than 16 bits
+    BadMaxval(u32),
+    /// the PAM tuple type, or its depth, is not one we know how to show
+    UnsupportedTupleType(String),
+    /// a sample is not a number, or is larger than the maxval
+    BadSample(String),
+    /// the data ends before all samples are read
+    Truncated,
+}
+
+impl fmt::Display for PnmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PnmError::Io(e) => write!(f, "cannot read Netpbm data: {}", e),
+            PnmError::UnknownFormat => write!(f, "not a Netpbm image"),
+            PnmError::BadHeader(field) => write!(f, "bad header field {:?}", field),
+            PnmError::BadMaxval(m) => write!(f, "bad maxval {}", m),
+            PnmError::UnsupportedTupleType(t) => write!(f, "unsupported tuple type {:?}", t),
+            PnmError::BadSample(s) => write!(f, "bad sample {:?}", s),
+            PnmError::Truncated => write!(f, "truncated pixel data"),
+        }
+    }
+}
+
+impl error::Error for PnmError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PnmError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// What we need to know from the header to read the samples.
+struct PnmHeader {
+    width: usize,
+    height: usize,
+    /// samples per pixel
+    depth: usize,
+    maxval: u32,
+    /// bitmaps and PAM black and white use 0 and 1 samples, but mean opposite things
+    one_is_black: bool,
+}
+
+impl PnmHeader {
+    /// Turn the samples for a pixel into a color, scaling them from 0..=maxval.
+    fn color(&self, tuple: &[u32]) -> Rgba {
+        let scale = |v: u32| ((v as u64 * 255 + self.maxval as u64 / 2) / self.maxval as u64) as u8;
+        let scaled = |v: u32| {
+            if self.one_is_black {
+                255 - scale(v)
+            } else {
+                scale(v)
+            }
+        };
+        match self.depth {
+            1 => {
+                let gray = scaled(tuple[0]);
+                [gray, gray, gray, 255]
+            }
+            // alpha is never inverted, 0 is transparent for bitmaps too
+            2 => {
+                let gray = scaled(tuple[0]);
+                [gray, gray, gray, scale(tuple[1])]
+            }
+            3 => [scaled(tuple[0]), scaled(tuple[1]), scaled(tuple[2]), 255],
+            _ => [
+                scaled(tuple[0]),
+                scaled(tuple[1]),
+                scaled(tuple[2]),
+                scale(tuple[3]),
+            ],
+        }
+    }
+
+    /// how many samples the image has, or `Truncated` if it has too many to
+    /// count, as there can't be enough data for them
+    fn samples(&self) -> Result<usize, PnmError> {
+        self.width
+            .checked_mul(self.height)
+            .and_then(|pixels| pixels.checked_mul(self.depth))
+            .ok_or(PnmError::Truncated)
+    }
+}
+
+struct PnmParser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PnmParser<'a> {
+    fn magic(&mut self) -> Result<u8, PnmError> {
+        match self.data {
+            [b'P', m @ b'1'..=b'7', ..] => {
+                self.pos = 2;
+                Ok(*m)
+            }
+            _ => Err(PnmError::UnknownFormat),
+        }
+    }
+
+    /// Skip whitespace, and comments from # to the end of the line.
+    fn skip_space(&mut self) {
+        while let Some(&c) = self.data.get(self.pos) {
+            if c == b'#' {
+                while self.pos < self.data.len() && self.data[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn token(&mut self) -> Option<&'a str> {
+        self.skip_space();
+        let start = self.pos;
+        while self.pos < self.data.len() && !self.data[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            None
+        } else {
+            std::str::from_utf8(&self.data[start..self.pos]).ok()
+        }
+    }
+
+    fn header_number(&mut self, field: &str) -> Result<u32, PnmError> {
+        let token = self
+            .token()
+            .ok_or_else(|| PnmError::BadHeader(field.to_owned()))?;
+        token
+            .parse()
+            .map_err(|_| PnmError::BadHeader(format!("{} {}", field, token)))
+    }
+
+    /// Parse the header of P1 to P6 images, which is just whitespace separated numbers.
+    fn pnm_header(&mut self, magic: u8) -> Result<PnmHeader, PnmError> {
+        let width = self.header_number("width")? as usize;
+        let height = self.header_number("height")? as usize;
+        let is_bitmap = magic == b'1' || magic == b'4';
+        let maxval = if is_bitmap {
+            1
+        } else {
+            check_maxval(self.header_number("maxval")?)?
+        };
+        // a single whitespace character separates the header from binary data
+        self.pos += 1;
+        let depth = if magic == b'3' || magic == b'6' { 3 } else { 1 };
+        Ok(PnmHeader {
+            width,
+            height,
+            depth,
+            maxval,
+            one_is_black: is_bitmap,
+        })
+    }
+
+    /// Parse the header of P7 images, which is lines of names and values.
+    fn pam_header(&mut self) -> Result<PnmHeader, PnmError> {
+        let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+        let mut tuple_type = String::new();
+        loop {
+            match self.token() {
+                Some("WIDTH") => width = Some(self.header_number("WIDTH")? as usize),
+                Some("HEIGHT") => height = Some(self.header_number("HEIGHT")? as usize),
+                Some("DEPTH") => depth = Some(self.header_number("DEPTH")? as usize),
+                Some("MAXVAL") => maxval = Some(check_maxval(self.header_number("MAXVAL")?)?),
+                Some("TUPLTYPE") => {
+                    // the tuple type is the rest of the line, and may be given in parts
+                    self.skip_space();
+                    let start = self.pos;
+                    while self.pos < self.data.len() && self.data[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                    let part = String::from_utf8_lossy(&self.data[start..self.pos]);
+                    if !tuple_type.is_empty() {
+                        tuple_type.push(' ');
+                    }
+                    tuple_type.push_str(part.trim());
+                }
+                Some("ENDHDR") => break,
+                Some(field) => return Err(PnmError::BadHeader(field.to_owned())),
+                None => return Err(PnmError::BadHeader("ENDHDR".to_owned())),
+            }
+        }
+        // the header ends with a newline after ENDHDR
+        self.pos += 1;
+
+        let missing = |field: &str| PnmError::BadHeader(field.to_owned());
+        let width = width.ok_or_else(|| missing("WIDTH"))?;
+        let height = height.ok_or_else(|| missing("HEIGHT"))?;
+        let depth = depth.ok_or_else(|| missing("DEPTH"))?;
+        let maxval = maxval.ok_or_else(|| missing("MAXVAL"))?;
+        let supported = match tuple_type.as_str() {
+            "BLACKANDWHITE" | "GRAYSCALE" => depth == 1,
+            "BLACKANDWHITE_ALPHA" | "GRAYSCALE_ALPHA" => depth == 2,
+            "RGB" => depth == 3,
+            "RGB_ALPHA" => depth == 4,
+            // without a tuple type, guess from the depth
+            "" => (1..=4).contains(&depth),
+            _ => false,
+        };
+        if !supported {
+            return Err(PnmError::UnsupportedTupleType(format!(
+                "{} with depth {}",
+                tuple_type, depth
+            )));
+        }
+        Ok(PnmHeader {
+            width,
+            height,
+            depth,
+            maxval,
+            one_is_black: false,
+        })
+    }
+
+    /// P1 samples are 0 or 1 characters, whitespace between them is optional.
+    fn ascii_bits(&mut self, header: &PnmHeader) -> Result<Vec<u32>, PnmError> {
+        let count = self.ascii_count(header)?;
+        let mut samples = Vec::with_capacity(count);
+        while samples.len() < count {
+            self.skip_space();
+            match self.data.get(self.pos) {
+                Some(b'0') => samples.push(0),
+                Some(b'1') => samples.push(1),
+                Some(&c) => return Err(PnmError::BadSample((c as char).to_string())),
+                None => return Err(PnmError::Truncated),
+            }
+            self.pos += 1;
+        }
+        Ok(samples)
+    }
+
+    fn ascii_samples(&mut self, header: &PnmHeader) -> Result<Vec<u32>, PnmError> {
+        let count = self.ascii_count(header)?;
+        let mut samples = Vec::with_capacity(count);
+        while samples.len() < count {
+            let token = self.token().ok_or(PnmError::Truncated)?;
+            match token.parse() {
+                Ok(v) if v <= header.maxval => samples.push(v),
+                _ => return Err(PnmError::BadSample(token.to_owned())),
+            }
+        }
+        Ok(samples)
+    }
+
+    /// How many samples the ASCII image has, checking that the data has room
+    /// for them, as each takes a character at least.
+    fn ascii_count(&self, header: &PnmHeader) -> Result<usize, PnmError> {
+        match header.samples()? {
+            count if count <= self.data.len() - self.pos.min(self.data.len()) => Ok(count),
+            _ => Err(PnmError::Truncated),
+        }
+    }
+
+    /// P4 samples are packed 8 to a byte, each row starting on a new byte.
+    fn binary_bits(&mut self, header: &PnmHeader) -> Result<Vec<u32>, PnmError> {
+        let stride = (header.width + 7) / 8;
+        let length = stride
+            .checked_mul(header.height)
+            .ok_or(PnmError::Truncated)?;
+        let raster = self.raster(length)?;
+        let mut samples = Vec::with_capacity(header.samples()?);
+        for row in raster.chunks_exact(stride.max(1)).take(header.height) {
+            for x in 0..header.width {
+                samples.push(((row[x / 8] >> (7 - x % 8)) & 1) as u32);
+            }
+        }
+        Ok(samples)
+    }
+
+    /// Samples are one byte, or two in big endian order if the maxval needs it.
+    fn binary_samples(&mut self, header: &PnmHeader) -> Result<Vec<u32>, PnmError> {
+        let bytes = if header.maxval > 255 { 2 } else { 1 };
+        let length = header
+            .samples()?
+            .checked_mul(bytes)
+            .ok_or(PnmError::Truncated)?;
+        let raster = self.raster(length)?;
+        let samples: Vec<u32> = if bytes == 2 {
+            raster
+                .chunks_exact(2)
+                .map(|s| u16::from_be_bytes([s[0], s[1]]) as u32)
+                .collect()
+        } else {
+            raster.iter().map(|&s| s as u32).collect()
+        };
+        // they would wrap around when scaled
+        match samples.iter().find(|&&s| s > header.maxval) {
+            Some(s) => Err(PnmError::BadSample(s.to_string())),
+            None => Ok(samples),
+        }
+    }
+
+    fn raster(&mut self, len: usize) -> Result<&'a [u8], PnmError> {
+        let raster = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or(PnmError::Truncated)?;
+        self.pos += len;
+        Ok(raster)
+    }
+}
+
+fn check_maxval(maxval: u32) -> Result<u32, PnmError> {
+    if maxval == 0 || maxval > 65535 {
+        Err(PnmError::BadMaxval(maxval))
+    } else {
+        Ok(maxval)
+    }
+}
+
+/// The brightness of a color, using the Rec. 601 weights.
+fn luma(color: Rgba) -> u8 {
+    let [r, g, b, _] = color;
+    (0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(data: &[u8]) -> Result<Image<Rgba>, PnmError> {
+        Image::from_pnm(&mut &data[..])
+    }
+
+    #[test]
+    fn reads_scaled_samples() {
+        let image = read(b"P5 2 1 1023\n\x03\xff\x01\x00").unwrap();
+        assert_eq!(image.pixels, [[255, 255, 255, 255], [64, 64, 64, 255]]);
+    }
+
+    #[test]
+    fn rejects_samples_above_maxval() {
+        for data in [
+            &b"P5 2 1 100\n\x10\x65"[..],
+            b"P5 1 1 1000\n\x03\xe9",
+            b"P2 1 1 100 101",
+        ]
+        .iter()
+        {
+            match read(data) {
+                Err(PnmError::BadSample(s)) => assert!(s == "101" || s == "1001", "{}", s),
+                other => panic!("expected a bad sample, got {:?}", other.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_huge_sizes_without_allocating() {
+        let headers: [&[u8]; 6] = [
+            b"P6 4000000000 4000000000 255\n\x00",
+            b"P5 65536 65536 65535\n\x00",
+            b"P4 4294967295 4294967295\n\x00",
+            b"P3 100000 100000 255\n1 2 3",
+            b"P1 4294967295 4294967295\n0",
+            b"P7\nWIDTH 4294967295\nHEIGHT 4294967295\nDEPTH 4\nMAXVAL 255\nENDHDR\n\x00",
+        ];
+        for header in headers.iter() {
+            match read(header) {
+                Err(PnmError::Truncated) => {}
+                other => panic!("expected truncated data, got {:?}", other.err()),
+            }
         }
     }
 }
@@ -0,0 +1,619 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Reading [PLY] files, the polygon files scanners and photogrammetry tools export.
+//!
+//! [PLY]: https://en.wikipedia.org/wiki/PLY_(file_format)
+//!
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::str::SplitAsciiWhitespace;
+
+use crate::geometry::Vec3;
+use crate::geometry::Vec3f;
+use crate::image::Rgba;
+use crate::model::triangulate;
+use crate::model::Face;
+use crate::model::Model;
+use crate::model::NormalMode;
+
+impl Model {
+    /// Parse the given PLY data, ASCII or binary in either byte order, into a model.
+    ///
+    /// Vertices must have `x`, `y`, and `z`, and may have normals, `nx`, `ny`,
+    /// and `nz`, uv coords, `s` and `t` or `u` and `v`, and sRGB colors, `red`,
+    /// `green`, `blue`, and `alpha`. Faces are lists of vertex indices, in
+    /// `vertex_indices` or `vertex_index`, polygons are split into triangles.
+    /// Other elements and properties are skipped. Without normals in the file,
+    /// normals are generated, see `NormalMode::default`.
+    pub fn from_ply<R: Read>(data: &mut R) -> Result<Model, PlyError> {
+        let mut bytes = Vec::new();
+        data.read_to_end(&mut bytes).map_err(PlyError::Io)?;
+        let (header, body_start) = Header::parse(&bytes)?;
+        let body = &bytes[body_start..];
+        let ascii_body;
+        let mut body = match header.format {
+            Format::Ascii => {
+                ascii_body = String::from_utf8_lossy(body);
+                Body::Ascii(ascii_body.split_ascii_whitespace())
+            }
+            Format::BinaryLittleEndian => Body::Binary {
+                data: body,
+                pos: 0,
+                big_endian: false,
+            },
+            Format::BinaryBigEndian => Body::Binary {
+                data: body,
+                pos: 0,
+                big_endian: true,
+            },
+        };
+
+        let mut vertices = Vertices::default();
+        let mut polygons = Vec::new();
+        for element in &header.elements {
+            match element.name.as_str() {
+                "vertex" => vertices = Vertices::read(element, &mut body)?,
+                "face" => polygons = read_faces(element, &mut body)?,
+                // elements without properties take no room, however many there are
+                _ if element.properties.is_empty() => {}
+                _ => {
+                    for _ in 0..element.count {
+                        for property in &element.properties {
+                            body.property(property)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut faces = Vec::new();
+        for polygon in polygons {
+            if let Some(&index) = polygon.iter().find(|&&i| i >= vertices.verts.len()) {
+                return Err(PlyError::IndexOutOfRange {
+                    index,
+                    len: vertices.verts.len(),
+                });
+            }
+            let points: Vec<Vec3f> = polygon.iter().map(|&i| vertices.verts[i]).collect();
+            for [a, b, c] in triangulate(&points) {
+                let verts = Vec3::new(polygon[a], polygon[b], polygon[c]);
+                faces.push(Face {
+                    verts,
+                    uvs: Some(verts).filter(|_| !vertices.uvs.is_empty()),
+                    norms: Some(verts).filter(|_| !vertices.norms.is_empty()),
+                    material: None,
+                    smoothing_group: 0,
+                });
+            }
+        }
+        let mut model = Model::from_faces(
+            vertices.verts,
+            vertices.uvs,
+            vertices.norms,
+            faces,
+            NormalMode::default(),
+        );
+        if !vertices.colors.is_empty() {
+            model.set_colors(vertices.colors);
+        }
+        Ok(model)
+    }
+}
+
+/// Why PLY data could not be parsed.
+#[derive(Debug)]
+pub enum PlyError {
+    /// the data could not be read
+    Io(io::Error),
+    /// the data does not start with `ply`
+    BadSignature,
+    /// the format is not ASCII or binary, version 1.0
+    UnsupportedFormat(String),
+    /// a header line is not one we understand
+    BadHeader(String),
+    /// the vertices lack a property we need, like `x`, or faces lack their indices
+    MissingProperty(&'static str),
+    /// a value in ASCII data is not a number
+    BadValue(String),
+    /// a face refers to a vertex that is not there
+    IndexOutOfRange { index: usize, len: usize },
+    /// the data ends before all elements are read
+    Truncated,
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlyError::Io(e) => write!(f, "cannot read PLY data: {}", e),
+            PlyError::BadSignature => write!(f, "not a PLY file"),
+            PlyError::UnsupportedFormat(format) => write!(f, "unsupported format {:?}", format),
+            PlyError::BadHeader(line) => write!(f, "bad header line {:?}", line),
+            PlyError::MissingProperty(name) => write!(f, "missing property {:?}", name),
+            PlyError::BadValue(token) => write!(f, "bad value {:?}", token),
+            PlyError::IndexOutOfRange { index, len } => write!(
+                f,
+                "vertex index {} out of range, there are {} vertices",
+                index, len
+            ),
+            PlyError::Truncated => write!(f, "truncated PLY data"),
+        }
+    }
+}
+
+impl error::Error for PlyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PlyError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+/// The types of values, which are all read as floats.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Type {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Type {
+    fn parse(name: &str) -> Option<Type> {
+        match name {
+            "char" | "int8" => Some(Type::I8),
+            "uchar" | "uint8" => Some(Type::U8),
+            "short" | "int16" => Some(Type::I16),
+            "ushort" | "uint16" => Some(Type::U16),
+            "int" | "int32" => Some(Type::I32),
+            "uint" | "uint32" => Some(Type::U32),
+            "float" | "float32" => Some(Type::F32),
+            "double" | "float64" => Some(Type::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8,
+        }
+    }
+
+    /// what a color value is multiplied by to go from 0 to 255,
+    /// floats go to 1.0, integers to their maximum, except for bytes
+    /// and bigger integers, which nobody uses beyond 255
+    fn color_scale(self) -> f64 {
+        match self {
+            Type::F32 | Type::F64 => 255.0,
+            Type::I16 | Type::U16 => 255.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar {
+        name: String,
+        kind: Type,
+    },
+    List {
+        name: String,
+        count: Type,
+        item: Type,
+    },
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar { name, .. } | Property::List { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    /// the index and type of the scalar property with one of the names
+    fn scalar(&self, names: &[&str]) -> Option<(usize, Type)> {
+        self.properties
+            .iter()
+            .enumerate()
+            .find_map(|(i, property)| match property {
+                Property::Scalar { name, kind } if names.contains(&name.as_str()) => {
+                    Some((i, *kind))
+                }
+                _ => None,
+            })
+    }
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+impl Header {
+    /// Parse the header, returning it and where the body starts.
+    fn parse(bytes: &[u8]) -> Result<(Header, usize), PlyError> {
+        let mut lines = HeaderLines { bytes, pos: 0 };
+        if lines.next().map(str::trim_end) != Some("ply") {
+            return Err(PlyError::BadSignature);
+        }
+        let mut format = None;
+        let mut elements: Vec<Element> = Vec::new();
+        loop {
+            let line = lines.next().ok_or(PlyError::Truncated)?;
+            let bad_header = || PlyError::BadHeader(line.to_owned());
+            let tokens: Vec<&str> = line.split_ascii_whitespace().collect();
+            match tokens.as_slice() {
+                ["format", name, "1.0"] => {
+                    format = Some(match *name {
+                        "ascii" => Format::Ascii,
+                        "binary_little_endian" => Format::BinaryLittleEndian,
+                        "binary_big_endian" => Format::BinaryBigEndian,
+                        _ => return Err(PlyError::UnsupportedFormat(line.to_owned())),
+                    })
+                }
+                ["format", ..] => return Err(PlyError::UnsupportedFormat(line.to_owned())),
+                ["element", name, count] => elements.push(Element {
+                    name: name.to_string(),
+                    count: count.parse().map_err(|_| bad_header())?,
+                    properties: Vec::new(),
+                }),
+                ["property", "list", count, item, name] => {
+                    let property = Property::List {
+                        name: name.to_string(),
+                        count: Type::parse(count).ok_or_else(bad_header)?,
+                        item: Type::parse(item).ok_or_else(bad_header)?,
+                    };
+                    let element = elements.last_mut().ok_or_else(bad_header)?;
+                    element.properties.push(property);
+                }
+                ["property", kind, name] => {
+                    let property = Property::Scalar {
+                        name: name.to_string(),
+                        kind: Type::parse(kind).ok_or_else(bad_header)?,
+                    };
+                    let element = elements.last_mut().ok_or_else(bad_header)?;
+                    element.properties.push(property);
+                }
+                ["comment", ..] | ["obj_info", ..] | [] => continue,
+                ["end_header"] => break,
+                _ => return Err(bad_header()),
+            }
+        }
+        let format = format.ok_or_else(|| PlyError::UnsupportedFormat(String::new()))?;
+        Ok((Header { format, elements }, lines.pos))
+    }
+}
+
+/// The lines of the header, which is ASCII even in binary files.
+struct HeaderLines<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> HeaderLines<'a> {
+    /// the next line, without the line break, or `None` at the end or
+    /// if the line is not text
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = &self.bytes[self.pos..];
+        let end = rest.iter().position(|&b| b == b'\n')?;
+        self.pos += end + 1;
+        std::str::from_utf8(&rest[..end])
+            .ok()
+            .map(|line| line.trim_end_matches('\r'))
+    }
+}
+
+/// The elements after the header.
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary {
+        data: &'a [u8],
+        pos: usize,
+        big_endian: bool,
+    },
+}
+
+impl Body<'_> {
+    fn value(&mut self, kind: Type) -> Result<f64, PlyError> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().ok_or(PlyError::Truncated)?;
+                token
+                    .parse()
+                    .map_err(|_| PlyError::BadValue(token.to_owned()))
+            }
+            Body::Binary {
+                data,
+                pos,
+                big_endian,
+            } => {
+                let bytes = data
+                    .get(*pos..*pos + kind.size())
+                    .ok_or(PlyError::Truncated)?;
+                *pos += kind.size();
+                let mut b = [0; 8];
+                b[..bytes.len()].copy_from_slice(bytes);
+                if *big_endian {
+                    b[..bytes.len()].reverse();
+                }
+                Ok(match kind {
+                    Type::I8 => b[0] as i8 as f64,
+                    Type::U8 => b[0] as f64,
+                    Type::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::F64 => f64::from_le_bytes(b),
+                })
+            }
+        }
+    }
+
+    /// Read a property, scalars as one value, lists as all of theirs.
+    fn property(&mut self, property: &Property) -> Result<Vec<f64>, PlyError> {
+        match property {
+            Property::Scalar { kind, .. } => Ok(vec![self.value(*kind)?]),
+            Property::List { count, item, .. } => {
+                let count = self.value(*count)?;
+                if count < 0.0 {
+                    return Err(PlyError::BadValue(count.to_string()));
+                }
+                (0..count as usize).map(|_| self.value(*item)).collect()
+            }
+        }
+    }
+}
+
+/// The vertex data we use.
+#[derive(Default)]
+struct Vertices {
+    verts: Vec<Vec3f>,
+    uvs: Vec<Vec3f>,
+    norms: Vec<Vec3f>,
+    colors: Vec<Rgba>,
+}
+
+impl Vertices {
+    fn read(element: &Element, body: &mut Body) -> Result<Vertices, PlyError> {
+        let required = |name: &'static str| {
+            element
+                .scalar(&[name])
+                .ok_or(PlyError::MissingProperty(name))
+        };
+        let position = [required("x")?, required("y")?, required("z")?];
+        let all = |found: Vec<Option<(usize, Type)>>| found.into_iter().collect::<Option<Vec<_>>>();
+        let normal = all(vec![
+            element.scalar(&["nx"]),
+            element.scalar(&["ny"]),
+            element.scalar(&["nz"]),
+        ]);
+        let uv = all(vec![
+            element.scalar(&["s", "u", "texture_u"]),
+            element.scalar(&["t", "v", "texture_v"]),
+        ]);
+        let color = all(vec![
+            element.scalar(&["red", "r"]),
+            element.scalar(&["green", "g"]),
+            element.scalar(&["blue", "b"]),
+        ]);
+        let alpha = element.scalar(&["alpha", "a"]);
+
+        let mut vertices = Vertices::default();
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            for (value, property) in values.iter_mut().zip(element.properties.iter()) {
+                // lists in vertices are not something we use
+                *value = body.property(property)?.first().copied().unwrap_or(0.0);
+            }
+            let vector = |p: &[(usize, Type)]| {
+                Vec3f::new(
+                    values[p[0].0],
+                    values[p[1].0],
+                    p.get(2).map_or(0.0, |c| values[c.0]),
+                )
+            };
+            vertices.verts.push(vector(&position));
+            if let Some(normal) = &normal {
+                vertices.norms.push(vector(normal));
+            }
+            if let Some(uv) = &uv {
+                vertices.uvs.push(vector(uv));
+            }
+            if let Some(color) = &color {
+                let channel = |(i, kind): (usize, Type)| {
+                    (values[i] * kind.color_scale()).round().clamp(0.0, 255.0) as u8
+                };
+                vertices.colors.push([
+                    channel(color[0]),
+                    channel(color[1]),
+                    channel(color[2]),
+                    alpha.map_or(255, channel),
+                ]);
+            }
+        }
+        Ok(vertices)
+    }
+}
+
+/// Read the faces, as lists of vertex indices.
+fn read_faces(element: &Element, body: &mut Body) -> Result<Vec<Vec<usize>>, PlyError> {
+    let indices = element
+        .properties
+        .iter()
+        .position(|p| {
+            matches!(p, Property::List { .. })
+                && (p.name() == "vertex_indices" || p.name() == "vertex_index")
+        })
+        .ok_or(PlyError::MissingProperty("vertex_indices"))?;
+    // the count is only the header's word, so grow as the faces are there
+    let mut faces = Vec::new();
+    for _ in 0..element.count {
+        for (i, property) in element.properties.iter().enumerate() {
+            let values = body.property(property)?;
+            if i != indices {
+                continue;
+            }
+            if values.iter().any(|&v| v < 0.0 || v.fract() != 0.0) {
+                return Err(PlyError::BadValue(format!("{:?}", values)));
+            }
+            // scanners leave behind some faces without an area, they draw nothing
+            if values.len() >= 3 {
+                faces.push(values.iter().map(|&v| v as usize).collect());
+            }
+        }
+    }
+    Ok(faces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(ply: &[u8]) -> Model {
+        Model::from_ply(&mut &ply[..]).unwrap()
+    }
+
+    #[test]
+    fn reads_ascii() {
+        let model = read(
+            b"ply\nformat ascii 1.0\ncomment a square\n\
+              element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+              property float nx\nproperty float ny\nproperty float nz\n\
+              property float s\nproperty float t\n\
+              element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+              0 0 0 0 0 1 0 0\n1 0 0 0 0 1 1 0\n1 1 0 0 0 1 1 1\n0 1 0 0 0 1 0 1\n\
+              4 0 1 2 3\n",
+        );
+        assert_eq!(model.nfaces(), 2, "the square is split into triangles");
+        for f in 0..2 {
+            for v in 0..3 {
+                let (p, uv, n) = (model.fvert(f, v), model.fuv(f, v), model.fnorm(f, v));
+                assert_eq!((p.x, p.y, p.z), (uv.x, uv.y, 0.0));
+                assert_eq!((n.x, n.y, n.z), (0.0, 0.0, 1.0));
+            }
+        }
+        assert!(!model.has_colors());
+    }
+
+    #[test]
+    fn reads_binary_big_endian() {
+        let mut ply = b"ply\nformat binary_big_endian 1.0\n\
+            element vertex 3\nproperty double x\nproperty float y\nproperty short z\n\
+            element face 1\nproperty uchar flags\nproperty list ushort uint vertex_index\n\
+            end_header\n"
+            .to_vec();
+        for &(x, y, z) in &[(0.5f64, -1.0f32, 7i16), (1.5, 0.0, -2), (0.5, 2.0, 300)] {
+            ply.extend_from_slice(&x.to_be_bytes());
+            ply.extend_from_slice(&y.to_be_bytes());
+            ply.extend_from_slice(&z.to_be_bytes());
+        }
+        ply.push(0xff);
+        ply.extend_from_slice(&3u16.to_be_bytes());
+        for &i in &[2u32, 0, 1] {
+            ply.extend_from_slice(&i.to_be_bytes());
+        }
+        let model = read(&ply);
+        assert_eq!(model.nfaces(), 1);
+        let corners: Vec<(f64, f64, f64)> = (0..3)
+            .map(|v| model.fvert(0, v))
+            .map(|p| (p.x, p.y, p.z))
+            .collect();
+        assert_eq!(
+            corners,
+            [(0.5, 2.0, 300.0), (0.5, -1.0, 7.0), (1.5, 0.0, -2.0)]
+        );
+    }
+
+    #[test]
+    fn reads_vertex_colors() {
+        let colors = |properties: &str, values: &str| {
+            let model = read(
+                format!(
+                    "ply\nformat ascii 1.0\nelement vertex 3\n\
+                     property float x\nproperty float y\nproperty float z\n{}\
+                     element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                     0 0 0 {}\n1 0 0 {}\n0 1 0 {}\n3 0 1 2\n",
+                    properties, values, values, values
+                )
+                .as_bytes(),
+            );
+            model.fcolor(0, 0)
+        };
+        let bytes = "property uchar red\nproperty uchar green\nproperty uchar blue\n";
+        assert_eq!(colors(bytes, "255 128 0"), Some([255, 128, 0, 255]));
+        let floats = "property float r\nproperty float g\nproperty float b\nproperty float a\n";
+        assert_eq!(colors(floats, "1 0.5 0 0.25"), Some([255, 128, 0, 64]));
+        let shorts = "property ushort red\nproperty ushort green\nproperty ushort blue\n";
+        assert_eq!(colors(shorts, "65535 0 257"), Some([255, 0, 1, 255]));
+        assert_eq!(colors("", ""), None);
+    }
+
+    #[test]
+    fn skips_elements_without_properties_at_once() {
+        let model = read(
+            b"ply\nformat ascii 1.0\nelement nothing 18446744073709551615\n\
+              element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+              element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+              0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n",
+        );
+        assert_eq!(model.nfaces(), 1);
+    }
+
+    #[test]
+    fn huge_face_counts_need_their_faces() {
+        let mut ply = b"ply\nformat binary_little_endian 1.0\n\
+            element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 4000000000\nproperty list uchar int vertex_indices\nend_header\n"
+            .to_vec();
+        ply.extend_from_slice(&[0; 36]);
+        ply.push(3);
+        for i in 0..3i32 {
+            ply.extend_from_slice(&i.to_le_bytes());
+        }
+        assert!(matches!(
+            Model::from_ply(&mut &ply[..]),
+            Err(PlyError::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,750 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! [PNG] support, since that is what browsers want to show,
+//! and what most textures come as.
+//! Like for TGA, there are crates for this, but we get by with std.
+//!
+//! [PNG]: https://www.w3.org/TR/PNG/
+//!
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+
+use crate::image::ColorSpace;
+use crate::image::Image;
+use crate::image::Pixel;
+use crate::image::Rgba;
+use crate::zlib;
+use crate::zlib::InflateError;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// The 8 bit per channel pixel layouts we can write.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PngColorType {
+    Rgb,
+    Rgba,
+}
+
+impl PngColorType {
+    /// the color type number in the IHDR chunk
+    fn code(self) -> u8 {
+        match self {
+            PngColorType::Rgb => 2,
+            PngColorType::Rgba => 6,
+        }
+    }
+
+    fn channels(self) -> usize {
+        match self {
+            PngColorType::Rgb => 3,
+            PngColorType::Rgba => 4,
+        }
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    /// Save the bitmap data as an RGB PNG image, or RGBA if the image has alpha.
+    pub fn write_png<W: Write>(&self, writer: &mut BufWriter<W>) -> io::Result<()> {
+        let color_type = if P::CHANNELS == 4 {
+            PngColorType::Rgba
+        } else {
+            PngColorType::Rgb
+        };
+        let mut samples = Vec::with_capacity(self.size() * P::CHANNELS);
+        for p in &self.pixels {
+            samples.extend_from_slice(&p.to_rgba()[..P::CHANNELS]);
+        }
+        write_png_pixels(writer, self.width, self.height, color_type, &samples)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    /// Parse the given PNG data into a bitmap image.
+    ///
+    /// Grayscale, palette, RGB, gray with alpha, and RGBA images with
+    /// 8 bits per channel are supported, as well as grayscale and palette
+    /// images with fewer bits per pixel. Interlaced images are supported.
+    /// Alpha, from the alpha channel or a tRNS chunk, is kept if the image has
+    /// an alpha channel, and dropped otherwise. The image is tagged as sRGB.
+    pub fn from_png<R: Read>(data: &mut R) -> Result<Image<P>, PngError> {
+        let mut signature = [0; 8];
+        read_or_truncated(data, &mut signature)?;
+        if signature != SIGNATURE {
+            return Err(PngError::BadSignature);
+        }
+
+        let mut header: Option<PngHeader> = None;
+        let mut palette = Vec::new();
+        let mut color_key = None;
+        let mut compressed = Vec::new();
+        loop {
+            let (kind, chunk) = read_chunk(data)?;
+            match (&kind, &header) {
+                (b"IHDR", None) => header = Some(PngHeader::from_chunk(&chunk)?),
+                (_, None) => return Err(PngError::MissingHeader),
+                (b"PLTE", _) => {
+                    palette = chunk
+                        .chunks_exact(3)
+                        .map(|c| [c[0], c[1], c[2], 255])
+                        .collect()
+                }
+                (b"tRNS", Some(h)) => match h.color_type {
+                    // alpha for the first palette entries, the rest stay opaque
+                    COLOR_TYPE_PALETTE => {
+                        for (color, alpha) in palette.iter_mut().zip(chunk.iter()) {
+                            color[3] = *alpha;
+                        }
+                    }
+                    // a single gray level or color that is fully transparent
+                    COLOR_TYPE_GRAY if chunk.len() >= 2 => {
+                        let gray = u16::from_be_bytes([chunk[0], chunk[1]]);
+                        color_key = Some([gray; 3]);
+                    }
+                    COLOR_TYPE_RGB if chunk.len() >= 6 => {
+                        let sample = |i: usize| u16::from_be_bytes([chunk[i], chunk[i + 1]]);
+                        color_key = Some([sample(0), sample(2), sample(4)]);
+                    }
+                    _ => continue,
+                },
+                (b"IDAT", _) => compressed.extend_from_slice(&chunk),
+                (b"IEND", _) => break,
+                // chunks starting with an upper case letter are needed to show the image
+                _ if kind[0].is_ascii_uppercase() => {
+                    return Err(PngError::UnsupportedChunk(chunk_name(&kind)))
+                }
+                _ => continue,
+            }
+        }
+        let header = header.ok_or(PngError::MissingHeader)?;
+        if header.color_type == COLOR_TYPE_PALETTE && palette.is_empty() {
+            return Err(PngError::MissingPalette);
+        }
+
+        // the header says how much image data there is, inflating more is pointless
+        let length = header.data_length().ok_or(PngError::BadDimensions)?;
+        let scanlines = zlib::decompress_at_most(&compressed, length).map_err(PngError::Zlib)?;
+        header.decode(&scanlines, &palette, color_key)
+    }
+}
+
+/// Why PNG data could not be decoded.
+#[derive(Debug)]
+pub enum PngError {
+    /// the data could not be read, for other reasons than ending early
+    Io(io::Error),
+    /// the data does not start with the PNG signature
+    BadSignature,
+    /// the data ends in the middle of a chunk, or has too little image data
+    Truncated,
+    /// the first chunk is not the image header
+    MissingHeader,
+    /// the image size is zero, or unreasonably large
+    BadDimensions,
+    /// the checksum of the named chunk does not match its contents
+    CrcMismatch { chunk: String },
+    /// the named chunk is needed to show the image, but we can't handle it
+    UnsupportedChunk(String),
+    /// the color type is not one defined by the PNG spec
+    UnsupportedColorType(u8),
+    /// the bits per channel are not one we can decode for this color type
+    UnsupportedBitDepth(u8),
+    /// the compression, filter, or interlace method is not one defined by the PNG spec
+    UnsupportedMethod,
+    /// the image uses a palette, but there is none
+    MissingPalette,
+    /// a pixel refers to a color not in the palette
+    PaletteIndexOutOfRange(usize),
+    /// a scanline starts with an unknown filter type
+    BadFilter(u8),
+    /// the image data could not be decompressed
+    Zlib(InflateError),
+}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PngError::Io(e) => write!(f, "cannot read PNG data: {}", e),
+            PngError::BadSignature => write!(f, "not a PNG image"),
+            PngError::Truncated => write!(f, "truncated PNG data"),
+            PngError::MissingHeader => write!(f, "missing IHDR chunk"),
+            PngError::BadDimensions => write!(f, "bad image dimensions"),
+            PngError::CrcMismatch { chunk } => write!(f, "CRC mismatch in {} chunk", chunk),
+            PngError::UnsupportedChunk(chunk) => write!(f, "unsupported {} chunk", chunk),
+            PngError::UnsupportedColorType(t) => write!(f, "unsupported color type {}", t),
+            PngError::UnsupportedBitDepth(d) => write!(f, "unsupported bit depth {}", d),
+            PngError::UnsupportedMethod => {
+                write!(f, "unsupported compression, filter, or interlace method")
+            }
+            PngError::MissingPalette => write!(f, "missing PLTE chunk"),
+            PngError::PaletteIndexOutOfRange(i) => write!(f, "palette index {} out of range", i),
+            PngError::BadFilter(t) => write!(f, "bad filter type {}", t),
+            PngError::Zlib(e) => write!(f, "cannot decompress image data: {}", e),
+        }
+    }
+}
+
+impl error::Error for PngError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PngError::Io(e) => Some(e),
+            PngError::Zlib(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+fn read_or_truncated<R: Read>(data: &mut R, buf: &mut [u8]) -> Result<(), PngError> {
+    data.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => PngError::Truncated,
+        _ => PngError::Io(e),
+    })
+}
+
+/// Read a chunk, checking its CRC, and return its type and data.
+fn read_chunk<R: Read>(data: &mut R) -> Result<([u8; 4], Vec<u8>), PngError> {
+    let mut length = [0; 4];
+    let mut kind = [0; 4];
+    let mut crc = [0; 4];
+    read_or_truncated(data, &mut length)?;
+    read_or_truncated(data, &mut kind)?;
+    // don't trust the length enough to allocate it all up front
+    let length = u32::from_be_bytes(length) as u64;
+    let mut chunk = Vec::new();
+    data.take(length)
+        .read_to_end(&mut chunk)
+        .map_err(PngError::Io)?;
+    if chunk.len() as u64 != length {
+        return Err(PngError::Truncated);
+    }
+    read_or_truncated(data, &mut crc)?;
+    if crc32(&[&kind, &chunk]) != u32::from_be_bytes(crc) {
+        return Err(PngError::CrcMismatch {
+            chunk: chunk_name(&kind),
+        });
+    }
+    Ok((kind, chunk))
+}
+
+fn chunk_name(kind: &[u8; 4]) -> String {
+    String::from_utf8_lossy(kind).into_owned()
+}
+
+const COLOR_TYPE_GRAY: u8 = 0;
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_PALETTE: u8 = 3;
+const COLOR_TYPE_GRAY_ALPHA: u8 = 4;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+/// The [Adam7] passes, as the first pixel and the distance between pixels
+/// in x and y.
+///
+/// [Adam7]: https://www.w3.org/TR/PNG/#8Interlace
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+struct PngHeader {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl PngHeader {
+    fn from_chunk(chunk: &[u8]) -> Result<PngHeader, PngError> {
+        if chunk.len() != 13 {
+            return Err(PngError::Truncated);
+        }
+        let width = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+        let height = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+        let bit_depth = chunk[8];
+        let color_type = chunk[9];
+        let (compression, filter, interlace) = (chunk[10], chunk[11], chunk[12]);
+
+        // PNG allows up to 2^31 - 1, but that many pixels would not fit in memory anyway
+        if width == 0 || height == 0 || width > 1 << 24 || height > 1 << 24 {
+            return Err(PngError::BadDimensions);
+        }
+        let depth_ok = match color_type {
+            COLOR_TYPE_GRAY | COLOR_TYPE_PALETTE => [1, 2, 4, 8].contains(&bit_depth),
+            COLOR_TYPE_RGB | COLOR_TYPE_GRAY_ALPHA | COLOR_TYPE_RGBA => bit_depth == 8,
+            t => return Err(PngError::UnsupportedColorType(t)),
+        };
+        if !depth_ok {
+            return Err(PngError::UnsupportedBitDepth(bit_depth));
+        }
+        if compression != 0 || filter != 0 || interlace > 1 {
+            return Err(PngError::UnsupportedMethod);
+        }
+
+        Ok(PngHeader {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced: interlace == 1,
+        })
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_TYPE_GRAY | COLOR_TYPE_PALETTE => 1,
+            COLOR_TYPE_GRAY_ALPHA => 2,
+            COLOR_TYPE_RGB => 3,
+            _ => 4,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// The passes the image is stored in, the Adam7 ones if it is interlaced.
+    fn passes(&self) -> &'static [(usize, usize, usize, usize)] {
+        if self.interlaced {
+            &ADAM7[..]
+        } else {
+            &[(0, 0, 1, 1)][..]
+        }
+    }
+
+    /// The pixels and the bytes in each row of a pass.
+    fn pass_size(&self, &(x0, y0, dx, dy): &(usize, usize, usize, usize)) -> (usize, usize, usize) {
+        let pass_width = (self.width.saturating_sub(x0) + dx - 1) / dx;
+        let pass_height = (self.height.saturating_sub(y0) + dy - 1) / dy;
+        let stride = (pass_width * self.bits_per_pixel() + 7) / 8;
+        (pass_width, pass_height, stride)
+    }
+
+    /// How many bytes of scanlines the image has, each row with its filter byte,
+    /// or `None` if that is too many to count.
+    fn data_length(&self) -> Option<usize> {
+        let mut length = Some(0usize);
+        for pass in self.passes() {
+            let (pass_width, pass_height, stride) = self.pass_size(pass);
+            // empty passes don't even have filter bytes
+            if pass_width == 0 {
+                continue;
+            }
+            let pass_length = (stride + 1).checked_mul(pass_height);
+            length = length.and_then(|l| l.checked_add(pass_length?));
+        }
+        length
+    }
+
+    /// Unfilter the decompressed scanlines, pass by pass, and turn them into colors.
+    /// Gray or RGB samples equal to `color_key` are made transparent.
+    fn decode<P: Pixel>(
+        &self,
+        scanlines: &[u8],
+        palette: &[Rgba],
+        color_key: Option<[u16; 3]>,
+    ) -> Result<Image<P>, PngError> {
+        // filters work on whole bytes, so small pixels use the byte before
+        let filter_bpp = (self.bits_per_pixel() / 8).max(1);
+
+        // make sure all the rows are there before making room for the image,
+        // the header can say it is huge
+        match self.data_length() {
+            Some(length) if scanlines.len() >= length => {}
+            _ => return Err(PngError::Truncated),
+        }
+
+        let mut image = Image::filled(self.width, self.height, P::default());
+        // we don't read gAMA, sRGB, or ICC profile chunks, but sRGB is the default
+        image.color_space = ColorSpace::Srgb;
+        let mut data = scanlines;
+        for pass in self.passes() {
+            let &(x0, y0, dx, dy) = pass;
+            let (pass_width, pass_height, stride) = self.pass_size(pass);
+            if pass_width == 0 || pass_height == 0 {
+                continue;
+            }
+            let mut prev = vec![0; stride];
+            let mut row = vec![0; stride];
+            for j in 0..pass_height {
+                let filter = Filter::from_u8(data[0])?;
+                unfilter(filter, &data[1..=stride], &prev, filter_bpp, &mut row);
+                data = &data[stride + 1..];
+
+                let y = y0 + j * dy;
+                for i in 0..pass_width {
+                    let x = x0 + i * dx;
+                    let color = self.color_at(&row, i, palette, color_key)?;
+                    image.pixels[y * self.width + x] = P::from_rgba(color);
+                }
+                std::mem::swap(&mut prev, &mut row);
+            }
+        }
+        Ok(image)
+    }
+
+    /// The color of pixel `i` in an unfiltered row.
+    fn color_at(
+        &self,
+        row: &[u8],
+        i: usize,
+        palette: &[Rgba],
+        color_key: Option<[u16; 3]>,
+    ) -> Result<Rgba, PngError> {
+        // fully transparent if the samples are the color key
+        let alpha = |samples: [u16; 3]| {
+            if color_key == Some(samples) {
+                0
+            } else {
+                255
+            }
+        };
+        if self.bit_depth < 8 {
+            // pack pixels into bytes, leftmost pixel in the most significant bits
+            let depth = self.bit_depth as usize;
+            let per_byte = 8 / depth;
+            let shift = 8 - depth * (i % per_byte + 1);
+            let value = (row[i / per_byte] >> shift) & ((1 << depth) - 1);
+            return match self.color_type {
+                COLOR_TYPE_PALETTE => palette_lookup(palette, value as usize),
+                _ => {
+                    let gray = (value as usize * 255 / ((1 << depth) - 1)) as u8;
+                    Ok([gray, gray, gray, alpha([value as u16; 3])])
+                }
+            };
+        }
+        let p = &row[i * self.channels()..];
+        match self.color_type {
+            COLOR_TYPE_PALETTE => palette_lookup(palette, p[0] as usize),
+            COLOR_TYPE_GRAY => Ok([p[0], p[0], p[0], alpha([p[0] as u16; 3])]),
+            COLOR_TYPE_GRAY_ALPHA => Ok([p[0], p[0], p[0], p[1]]),
+            COLOR_TYPE_RGB => {
+                let key = [p[0] as u16, p[1] as u16, p[2] as u16];
+                Ok([p[0], p[1], p[2], alpha(key)])
+            }
+            _ => Ok([p[0], p[1], p[2], p[3]]),
+        }
+    }
+}
+
+fn palette_lookup(palette: &[Rgba], index: usize) -> Result<Rgba, PngError> {
+    palette
+        .get(index)
+        .copied()
+        .ok_or(PngError::PaletteIndexOutOfRange(index))
+}
+
+fn unfilter(filter: Filter, filtered: &[u8], prev: &[u8], bpp: usize, row: &mut [u8]) {
+    for i in 0..filtered.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        row[i] = filtered[i].wrapping_add(filter.predict(a, prev[i], c));
+    }
+}
+
+/// Save 8 bit samples, top row first, as a PNG image.
+pub fn write_png_pixels<W: Write>(
+    writer: &mut BufWriter<W>,
+    width: usize,
+    height: usize,
+    color_type: PngColorType,
+    samples: &[u8],
+) -> io::Result<()> {
+    let stride = width * color_type.channels();
+    assert_eq!(samples.len(), stride * height, "wrong number of samples");
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth, color type, compression, filter, and interlace method
+    ihdr.extend_from_slice(&[8, color_type.code(), 0, 0, 0]);
+
+    // every scanline starts with its filter type
+    let bpp = color_type.channels();
+    let mut scanlines = Vec::with_capacity((stride + 1) * height);
+    let mut filtered = vec![0; stride];
+    let no_row = vec![0; stride];
+    for r in 0..height {
+        let row = &samples[r * stride..(r + 1) * stride];
+        let prev = if r > 0 {
+            &samples[(r - 1) * stride..r * stride]
+        } else {
+            &no_row
+        };
+        let filter = best_filter(row, prev, bpp, &mut filtered);
+        scanlines.push(filter as u8);
+        scanlines.extend_from_slice(&filtered);
+    }
+
+    writer.write_all(&SIGNATURE)?;
+    write_chunk(writer, b"IHDR", &ihdr)?;
+    write_chunk(writer, b"IDAT", &zlib::compress(&scanlines))?;
+    write_chunk(writer, b"IEND", &[])
+}
+
+/// The five ways a PNG scanline can be filtered, each predicting a sample from its
+/// neighbours to the left (a), above (b), and above left (c), so that only the
+/// difference to the prediction needs to be stored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Filter {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl Filter {
+    const ALL: [Filter; 5] = [
+        Filter::None,
+        Filter::Sub,
+        Filter::Up,
+        Filter::Average,
+        Filter::Paeth,
+    ];
+
+    fn from_u8(filter: u8) -> Result<Filter, PngError> {
+        Filter::ALL
+            .get(filter as usize)
+            .copied()
+            .ok_or(PngError::BadFilter(filter))
+    }
+
+    fn predict(self, a: u8, b: u8, c: u8) -> u8 {
+        match self {
+            Filter::None => 0,
+            Filter::Sub => a,
+            Filter::Up => b,
+            Filter::Average => ((a as u16 + b as u16) / 2) as u8,
+            Filter::Paeth => paeth(a, b, c),
+        }
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Filter the row with the filter that gives the smallest sum of absolute differences,
+/// the heuristic suggested by the PNG spec.
+fn best_filter(row: &[u8], prev: &[u8], bpp: usize, filtered: &mut [u8]) -> Filter {
+    let mut best = (Filter::None, u64::MAX);
+    for &filter in Filter::ALL.iter() {
+        apply_filter(filter, row, prev, bpp, filtered);
+        let cost = filtered
+            .iter()
+            .map(|&d| (d as i8).unsigned_abs() as u64)
+            .sum();
+        if cost < best.1 {
+            best = (filter, cost);
+        }
+    }
+    apply_filter(best.0, row, prev, bpp, filtered);
+    best.0
+}
+
+fn apply_filter(filter: Filter, row: &[u8], prev: &[u8], bpp: usize, filtered: &mut [u8]) {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        filtered[i] = row[i].wrapping_sub(filter.predict(a, prev[i], c));
+    }
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(&[kind, data]);
+    writer.write_all(&crc.to_be_bytes())
+}
+
+/// The [CRC-32] every chunk ends with, calculated over its type and data.
+///
+/// [CRC-32]: https://www.w3.org/TR/PNG/#D-CRCAppendix
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for part in parts {
+        for &byte in *part {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Color;
+
+    /// A PNG image of the given size and color type, with the scanlines
+    /// and chunks before them given as they are.
+    fn png(
+        (width, height): (u32, u32),
+        (bit_depth, color_type, interlace): (u8, u8, u8),
+        chunks: &[(&[u8; 4], &[u8])],
+        scanlines: &[u8],
+    ) -> Vec<u8> {
+        let mut ihdr = width.to_be_bytes().to_vec();
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        let mut data = SIGNATURE.to_vec();
+        write_chunk(&mut data, b"IHDR", &ihdr).unwrap();
+        for (kind, chunk) in chunks {
+            write_chunk(&mut data, kind, chunk).unwrap();
+        }
+        write_chunk(&mut data, b"IDAT", &zlib::compress(scanlines)).unwrap();
+        write_chunk(&mut data, b"IEND", &[]).unwrap();
+        data
+    }
+
+    fn write<P: Pixel>(image: &Image<P>) -> Vec<u8> {
+        let mut writer = BufWriter::new(Vec::new());
+        image.write_png(&mut writer).unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn round_trips_rgb_and_rgba() {
+        let mut rgb: Image<Color> = Image::filled(5, 4, [0; 3]);
+        for (i, p) in rgb.pixels.iter_mut().enumerate() {
+            *p = [i as u8 * 10, 255 - i as u8, (i % 3) as u8 * 100];
+        }
+        let read: Image<Color> = Image::from_png(&mut write(&rgb).as_slice()).unwrap();
+        assert_eq!((read.width, read.height), (5, 4));
+        assert_eq!(read.pixels, rgb.pixels);
+        assert_eq!(read.color_space, ColorSpace::Srgb);
+
+        let mut rgba: Image<Rgba> = Image::filled(3, 3, [0; 4]);
+        for (i, p) in rgba.pixels.iter_mut().enumerate() {
+            *p = [i as u8, 2 * i as u8, 3 * i as u8, 255 - 20 * i as u8];
+        }
+        let read: Image<Rgba> = Image::from_png(&mut write(&rgba).as_slice()).unwrap();
+        assert_eq!(read.pixels, rgba.pixels);
+    }
+
+    #[test]
+    fn reads_interlaced_images() {
+        // a 3x3 gray image, whose pixels are 10 times their index, in Adam7
+        // passes 1, 4, 5, 6, and 7, the second and third are empty at this size
+        let v = |x: u8, y: u8| 10 * (y * 3 + x);
+        let passes: [&[u8]; 5] = [
+            &[0, v(0, 0)],
+            &[0, v(2, 0)],
+            &[0, v(0, 2), v(2, 2)],
+            &[0, v(1, 0), 0, v(1, 2)],
+            &[0, v(0, 1), v(1, 1), v(2, 1)],
+        ];
+        let scanlines = passes.concat();
+        let data = png((3, 3), (8, COLOR_TYPE_GRAY, 1), &[], &scanlines);
+        let image: Image<Color> = Image::from_png(&mut data.as_slice()).unwrap();
+        let grays: Vec<u8> = image.pixels.iter().map(|p| p[0]).collect();
+        assert_eq!(grays, [0, 10, 20, 30, 40, 50, 60, 70, 80]);
+    }
+
+    #[test]
+    fn reads_palettes_with_transparency() {
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let chunks: [(&[u8; 4], &[u8]); 2] = [(b"PLTE", &palette), (b"tRNS", &[128])];
+        // 2 bits per pixel, indices 0, 1, 2, 1
+        let data = png(
+            (4, 1),
+            (2, COLOR_TYPE_PALETTE, 0),
+            &chunks,
+            &[0, 0b00_01_10_01],
+        );
+        let image: Image<Rgba> = Image::from_png(&mut data.as_slice()).unwrap();
+        let expected = [
+            [255, 0, 0, 128],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [0, 255, 0, 255],
+        ];
+        assert_eq!(image.pixels, expected);
+
+        let data = png(
+            (4, 1),
+            (2, COLOR_TYPE_PALETTE, 0),
+            &chunks,
+            &[0, 0b11_00_00_00],
+        );
+        match Image::<Rgba>::from_png(&mut data.as_slice()) {
+            Err(PngError::PaletteIndexOutOfRange(3)) => {}
+            other => panic!("expected a bad palette index, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn rejects_crc_mismatches() {
+        let mut data = write(&Image::filled(2, 2, [1, 2, 3]));
+        // the signature, the IHDR chunk, and the IDAT length and type
+        data[8 + 25 + 8] ^= 1;
+        match Image::<Color>::from_png(&mut data.as_slice()) {
+            Err(PngError::CrcMismatch { chunk }) => assert_eq!(chunk, "IDAT"),
+            other => panic!("expected a CRC mismatch, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = write(&Image::filled(2, 2, [1, 2, 3]));
+        // without the IEND chunk
+        let truncated = &data[..data.len() - 12];
+        assert!(matches!(
+            Image::<Color>::from_png(&mut &truncated[..]),
+            Err(PngError::Truncated)
+        ));
+        // two rows, but image data for one
+        let data = png((1, 2), (8, COLOR_TYPE_GRAY, 0), &[], &[0, 5]);
+        assert!(matches!(
+            Image::<Color>::from_png(&mut data.as_slice()),
+            Err(PngError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_more_image_data_than_the_header_says() {
+        let data = png((1, 1), (8, COLOR_TYPE_GRAY, 0), &[], &[0, 5, 0, 6]);
+        assert!(matches!(
+            Image::<Color>::from_png(&mut data.as_slice()),
+            Err(PngError::Zlib(InflateError::TooLong))
+        ));
+    }
+}
@@ -22,6 +22,8 @@ use crate::geometry::Matrix;
 use crate::geometry::Vec3f;
 use crate::image::Color;
 use crate::image::Image;
+use crate::image::Pixel;
+use crate::image::Rgbaf;
 
 /// A shader can change vertices and fragments (pixels).
 pub trait Shader {
@@ -29,17 +31,118 @@ pub trait Shader {
     fn vertex(&mut self, face_i: usize, vert_i: usize) -> Vec3f; // let's hope float is fine
     /// What is the color the fragment at `bar`?  
     /// Returns true iff the fragment should be rendered.
+    /// Colors are in linear light, `triangle` encodes them for the canvas.
     fn fragment(&mut self, bar: Vec3f, color: &mut Color) -> bool;
+    /// Like `fragment`, but also told how `bar` changes to the next pixel in x
+    /// and in y, for shaders that need to know, like ones picking mipmap levels,
+    /// and giving the color as floats with an alpha for blending, so that it
+    /// can be brighter than white on HDR canvases.
+    /// This is what `triangle` calls, by default it calls `fragment`
+    /// and makes the fragment fully opaque.
+    fn fragment_with_derivatives(
+        &mut self,
+        bar: Vec3f,
+        _dbar_dx: Vec3f,
+        _dbar_dy: Vec3f,
+        color: &mut Rgbaf,
+    ) -> bool {
+        let mut rgb = [0, 0, 0];
+        let keep_fragment = self.fragment(bar, &mut rgb);
+        *color = rgb.to_rgbaf();
+        keep_fragment
+    }
+}
+
+/// How the color of a fragment is combined with the color already on the canvas.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Blend {
+    /// overwrite the canvas, alpha and all
+    Replace,
+    /// draw the fragment over the canvas, as opaque as its alpha says
+    AlphaOver,
+    /// add the fragment, scaled by its alpha, to the canvas, for glows and fire
+    Additive,
+    /// multiply the canvas by the fragment, as much as its alpha says, for tinting
+    Multiply,
+}
+
+impl Blend {
+    /// The new canvas color when `src` is drawn over `dst`.
+    /// Colors are not premultiplied by their alpha, canvases without alpha
+    /// count as fully opaque. Colors are not clamped, so HDR canvases
+    /// can add up to more than 1.0.
+    pub fn apply(self, src: Rgbaf, dst: Rgbaf) -> Rgbaf {
+        let src_alpha = src[3].clamp(0.0, 1.0);
+        let dst_alpha = dst[3].clamp(0.0, 1.0);
+        // how much of the destination shows through, for alpha over
+        let over_alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+        let mix = |s: f64, d: f64| match self {
+            Blend::Replace => s,
+            Blend::AlphaOver if over_alpha == 0.0 => 0.0,
+            Blend::AlphaOver => (s * src_alpha + d * dst_alpha * (1.0 - src_alpha)) / over_alpha,
+            Blend::Additive => d + s * src_alpha,
+            Blend::Multiply => d * (1.0 - src_alpha + s * src_alpha),
+        };
+        let alpha = match self {
+            Blend::Replace => src_alpha,
+            Blend::AlphaOver | Blend::Additive => over_alpha,
+            Blend::Multiply => dst_alpha,
+        };
+        [
+            mix(src[0], dst[0]),
+            mix(src[1], dst[1]),
+            mix(src[2], dst[2]),
+            alpha,
+        ]
+    }
+}
+
+/// How `triangle_with` draws fragments.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RenderState {
+    pub blend: Blend,
+    /// should drawn fragments update the zbuffer? Transparent passes usually
+    /// turn this off, so they don't hide each other
+    pub depth_write: bool,
+}
+
+impl Default for RenderState {
+    /// Opaque drawing, replacing the canvas color and writing depth.
+    fn default() -> Self {
+        RenderState {
+            blend: Blend::Replace,
+            depth_write: true,
+        }
+    }
 }
 
 /// Render a triangle v0,v1,v2 to the given canvas.
-pub fn triangle(
-    canvas: &mut Image,
-    zbuffer: &mut Vec<f64>,
+pub fn triangle<P: Pixel>(
+    canvas: &mut Image<P>,
+    zbuffer: &mut [f64],
     shader: &mut dyn Shader,
     v0: Vec3f,
     v1: Vec3f,
     v2: Vec3f,
+) {
+    triangle_with(canvas, zbuffer, shader, RenderState::default(), v0, v1, v2)
+}
+
+/// Render a triangle v0,v1,v2 to the given canvas, blending fragments with
+/// what is already there as the render state says.
+/// Fragments behind the zbuffer are never drawn, whether depth is written or not.
+///
+/// Fragment colors are encoded for the canvas's color space. Tagging the canvas
+/// and the textures as `ColorSpace::Linear` shades the stored values directly,
+/// like renders did before shading was done in linear light.
+pub fn triangle_with<P: Pixel>(
+    canvas: &mut Image<P>,
+    zbuffer: &mut [f64],
+    shader: &mut dyn Shader,
+    state: RenderState,
+    v0: Vec3f,
+    v1: Vec3f,
+    v2: Vec3f,
 ) {
     // figure out the bounding box
     let (mut xmin, mut ymin) = (f64::MAX, f64::MAX);
@@ -51,39 +154,59 @@ pub fn triangle(
         ymax = ymax.max(v.y);
     }
 
-    let xmin = (xmin as usize).max(0);
-    let xmax = (xmax as usize).min(canvas.width);
-    let ymin = (ymin as usize).max(0);
-    let ymax = (ymax as usize).min(canvas.height);
-
-    // for every pixel p inside the box ...
-    let mut p = Vec3f::zero();
-    for x in xmin..=xmax {
-        p.x = x as f64;
-        for y in ymin..=ymax {
-            p.y = y as f64;
-
-            // ... calculate the barycentric coordinates for p
-            let bc_screen = barycentric(v0, v1, v2, p);
-            // if any part of the coordinate is negative, p is outside the triangle
-            if bc_screen.x < 0. || bc_screen.y < 0. || bc_screen.z < 0. {
-                continue;
+    if canvas.width == 0 || canvas.height == 0 || xmax < 0. || ymax < 0. {
+        return;
+    }
+    // quads start on even pixels
+    let xmin = xmin as usize & !1;
+    let xmax = (xmax as usize).min(canvas.width - 1);
+    let ymin = ymin as usize & !1;
+    let ymax = (ymax as usize).min(canvas.height - 1);
+
+    // for every 2x2 quad of pixels inside the box ...
+    for qy in (ymin..=ymax).step_by(2) {
+        for qx in (xmin..=xmax).step_by(2) {
+            // ... calculate the barycentric coordinates for each pixel p,
+            // and how much they change from pixel to pixel
+            let quad_bc = |dx: usize, dy: usize| {
+                let p = Vec3f::new((qx + dx) as f64, (qy + dy) as f64, 0.);
+                barycentric(v0, v1, v2, p)
             };
-            p.z = v0.z * bc_screen.x;
-            p.z += v1.z * bc_screen.y;
-            p.z += v2.z * bc_screen.z;
-
-            // don't draw fragments behind something we have already drawn
-            let fragment_index = (p.x as usize) + (p.y as usize) * canvas.width;
-            if zbuffer[fragment_index] < p.z {
-                zbuffer[fragment_index] = p.z;
-
-                // apply fragment shader
-                let mut color = [0, 0, 0];
-                let keep_fragment = shader.fragment(bc_screen, &mut color);
-                // does this really play right with the z-index check before?
-                if keep_fragment {
-                    canvas.put(p.x as usize, p.y as usize, color);
+            let quad = [quad_bc(0, 0), quad_bc(1, 0), quad_bc(0, 1), quad_bc(1, 1)];
+            let dbar_dx = quad[1] - quad[0];
+            let dbar_dy = quad[2] - quad[0];
+
+            for (i, &bc_screen) in quad.iter().enumerate() {
+                let (x, y) = (qx + i % 2, qy + i / 2);
+                if x > xmax || y > ymax {
+                    continue;
+                }
+                // if any part of the coordinate is negative, p is outside the triangle
+                if bc_screen.x < 0. || bc_screen.y < 0. || bc_screen.z < 0. {
+                    continue;
+                };
+                let mut z = v0.z * bc_screen.x;
+                z += v1.z * bc_screen.y;
+                z += v2.z * bc_screen.z;
+
+                // don't draw fragments behind something we have already drawn
+                let fragment_index = x + y * canvas.width;
+                if zbuffer[fragment_index] < z {
+                    // apply fragment shader
+                    let mut color = [0.0, 0.0, 0.0, 1.0];
+                    let keep_fragment =
+                        shader.fragment_with_derivatives(bc_screen, dbar_dx, dbar_dy, &mut color);
+                    // discarded fragments don't hide anything behind them
+                    if keep_fragment {
+                        if state.depth_write {
+                            zbuffer[fragment_index] = z;
+                        }
+                        // blend in linear light, whatever the canvas stores
+                        let space = canvas.color_space;
+                        let canvas_color = space.to_linear(canvas.get(x, y).to_rgbaf());
+                        let blended = state.blend.apply(color, canvas_color);
+                        canvas.put(x, y, P::from_rgbaf(space.from_linear(blended)));
+                    }
                 }
             }
         }
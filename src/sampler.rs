@@ -0,0 +1,389 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Looking up texture colors by uv coordinates, with filtering and wrapping.
+//!
+
+use crate::geometry::Vec2f;
+use crate::image::Image;
+use crate::image::Pixel;
+use crate::image::Rgba;
+use crate::image::Rgbaf;
+use crate::mipmap::MipChain;
+
+/// How to pick a color between texels.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filter {
+    /// use the closest texel, blocky up close
+    Nearest,
+    /// blend the four closest texels
+    Bilinear,
+}
+
+/// How to pick a color between mipmap levels.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MipFilter {
+    /// always use the full size texture
+    None,
+    /// use the closest level
+    Nearest,
+    /// blend the two closest levels, trilinear filtering if the filter is bilinear
+    Linear,
+}
+
+/// What to do with coordinates outside 0.0 to 1.0.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Wrap {
+    /// tile the texture
+    Repeat,
+    /// repeat the edge texels
+    ClampToEdge,
+    /// tile the texture, every other tile mirrored, so there are no seams
+    MirroredRepeat,
+    /// use the sampler's border color
+    ClampToBorder,
+}
+
+/// Samples textures, with separate wrapping for u and v.
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+    /// the color outside the texture, for `Wrap::ClampToBorder`, in the texture's
+    /// color space, the alpha is dropped for textures without an alpha channel
+    pub border: Rgba,
+    /// how to use mipmaps, when sampling a `MipChain`
+    pub mip_filter: MipFilter,
+    /// how many samples to take along the direction the texture is stretched in,
+    /// 1 turns anisotropic filtering off
+    pub max_anisotropy: u32,
+}
+
+impl Default for Sampler {
+    /// Bilinear filtering, repeating the texture, blending mipmap levels.
+    fn default() -> Self {
+        Sampler::new(Filter::Bilinear, Wrap::Repeat)
+    }
+}
+
+impl Sampler {
+    /// A sampler wrapping both u and v the same way, with a transparent black border.
+    pub fn new(filter: Filter, wrap: Wrap) -> Sampler {
+        Sampler {
+            filter,
+            wrap_u: wrap,
+            wrap_v: wrap,
+            border: [0, 0, 0, 0],
+            mip_filter: MipFilter::Linear,
+            max_anisotropy: 1,
+        }
+    }
+
+    /// Get the color at `u`, `v` in the mipmapped texture, picking the level
+    /// from how much u and v change to the next pixel in x (`duv_dx`) and y (`duv_dy`).
+    pub fn sample_grad<P: Pixel>(
+        &self,
+        mips: &MipChain<P>,
+        u: f64,
+        v: f64,
+        duv_dx: Vec2f,
+        duv_dy: Vec2f,
+    ) -> Rgbaf {
+        // how many texels of the full size texture a pixel step covers
+        let base = mips.level(0);
+        let (w, h) = (base.width as f64, base.height as f64);
+        let px = (duv_dx.x * w).hypot(duv_dx.y * h);
+        let py = (duv_dy.x * w).hypot(duv_dy.y * h);
+        let (major, minor, axis) = if px >= py {
+            (px, py, duv_dx)
+        } else {
+            (py, px, duv_dy)
+        };
+
+        let samples = if self.max_anisotropy > 1 && minor > 0.0 {
+            ((major / minor).ceil() as u32).clamp(1, self.max_anisotropy)
+        } else {
+            1
+        };
+        if samples == 1 {
+            return self.sample_lod(mips, u, v, major.max(minor).log2());
+        }
+
+        // spread the samples along the direction of the major axis,
+        // using the level that fits the minor axis
+        let lod = (major / samples as f64).log2();
+        let mut sum = [0.0; 4];
+        for i in 0..samples {
+            let t = (i as f64 + 0.5) / samples as f64 - 0.5;
+            let color = self.sample_lod(mips, u + axis.x * t, v + axis.y * t, lod);
+            for (s, c) in sum.iter_mut().zip(color.iter()) {
+                *s += c;
+            }
+        }
+        for s in &mut sum {
+            *s /= samples as f64;
+        }
+        sum
+    }
+
+    /// Get the color at `u`, `v` in the mipmapped texture at the given
+    /// level of detail, 0.0 being the full size texture and 1.0 the next level.
+    pub fn sample_lod<P: Pixel>(&self, mips: &MipChain<P>, u: f64, v: f64, lod: f64) -> Rgbaf {
+        let max_level = (mips.nlevels() - 1) as f64;
+        // NaN from a zero derivative means the full size texture, like negative lods
+        let lod = if lod.is_nan() {
+            0.0
+        } else {
+            lod.clamp(0.0, max_level)
+        };
+        match self.mip_filter {
+            MipFilter::None => self.sample(mips.level(0), u, v),
+            MipFilter::Nearest => self.sample(mips.level(lod.round() as usize), u, v),
+            MipFilter::Linear => {
+                let level = lod.floor();
+                let t = lod - level;
+                let a = self.sample(mips.level(level as usize), u, v);
+                if t == 0.0 {
+                    return a;
+                }
+                let b = self.sample(mips.level(level as usize + 1), u, v);
+                lerp(a, b, t)
+            }
+        }
+    }
+
+    /// Get the color at `u`, `v` in the texture.
+    /// Like in OBJ files, u goes from left to right and v from the bottom up,
+    /// whether the texture is flipped or not.
+    /// The color is in linear light, texels are decoded from the texture's
+    /// color space before they are filtered.
+    pub fn sample<P: Pixel>(&self, texture: &Image<P>, u: f64, v: f64) -> Rgbaf {
+        let (w, h) = (texture.width as f64, texture.height as f64);
+        // texel coordinates, top row first like the pixels
+        let (x, y) = (u * w, (1.0 - v) * h);
+        match self.filter {
+            Filter::Nearest => self.texel(texture, x.floor() as i64, y.floor() as i64),
+            Filter::Bilinear => {
+                // texel centers are at .5, so shift to blend the closest four
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let top = lerp(
+                    self.texel(texture, x0, y0),
+                    self.texel(texture, x0 + 1, y0),
+                    fx,
+                );
+                let bottom = lerp(
+                    self.texel(texture, x0, y0 + 1),
+                    self.texel(texture, x0 + 1, y0 + 1),
+                    fx,
+                );
+                lerp(top, bottom, fy)
+            }
+        }
+    }
+
+    /// The texel at column `x` and row `y`, counted from the top, wrapped as needed,
+    /// in linear light.
+    fn texel<P: Pixel>(&self, texture: &Image<P>, x: i64, y: i64) -> Rgbaf {
+        let x = wrap(self.wrap_u, x, texture.width);
+        let y = wrap(self.wrap_v, y, texture.height);
+        let texel = match (x, y) {
+            (Some(x), Some(y)) => texture.pixels[x + y * texture.width],
+            _ => P::from_rgba(self.border),
+        };
+        texture.color_space.to_linear(texel.to_rgbaf())
+    }
+}
+
+/// Bring the texel index `i` into `0..size`, or `None` for the border.
+fn wrap(mode: Wrap, i: i64, size: usize) -> Option<usize> {
+    let n = size as i64;
+    let i = match mode {
+        Wrap::Repeat => i.rem_euclid(n),
+        Wrap::ClampToEdge => i.clamp(0, n - 1),
+        Wrap::MirroredRepeat => {
+            let m = i.rem_euclid(2 * n);
+            if m < n {
+                m
+            } else {
+                2 * n - 1 - m
+            }
+        }
+        Wrap::ClampToBorder => {
+            if i < 0 || i >= n {
+                return None;
+            }
+            i
+        }
+    };
+    Some(i as usize)
+}
+
+fn lerp(a: Rgbaf, b: Rgbaf, t: f64) -> Rgbaf {
+    let mut mixed = a;
+    for (m, b) in mixed.iter_mut().zip(b.iter()) {
+        *m += (b - *m) * t;
+    }
+    mixed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::HdrColor;
+
+    /// a linear texture of one row, with the given gray levels
+    fn row(grays: &[f32]) -> Image<HdrColor> {
+        let mut image = Image::filled(grays.len(), 1, [0.0; 3]);
+        for (pixel, &gray) in image.pixels.iter_mut().zip(grays) {
+            *pixel = [gray; 3];
+        }
+        image
+    }
+
+    fn assert_gray(color: Rgbaf, gray: f64) {
+        assert!(
+            (color[0] - gray).abs() < 1e-9,
+            "{:?} is not {}",
+            color,
+            gray
+        );
+    }
+
+    #[test]
+    fn wraps_every_mode() {
+        let wrapped = |mode| -> Vec<Option<usize>> { (-4..7).map(|i| wrap(mode, i, 3)).collect() };
+        let all = |indices: &[usize]| -> Vec<Option<usize>> {
+            indices.iter().map(|&i| Some(i)).collect()
+        };
+        assert_eq!(
+            wrapped(Wrap::Repeat),
+            all(&[2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0])
+        );
+        assert_eq!(
+            wrapped(Wrap::ClampToEdge),
+            all(&[0, 0, 0, 0, 0, 1, 2, 2, 2, 2, 2])
+        );
+        assert_eq!(
+            wrapped(Wrap::MirroredRepeat),
+            all(&[2, 2, 1, 0, 0, 1, 2, 2, 1, 0, 0])
+        );
+        let inside = [
+            None,
+            None,
+            None,
+            None,
+            Some(0),
+            Some(1),
+            Some(2),
+            None,
+            None,
+            None,
+            None,
+        ];
+        assert_eq!(wrapped(Wrap::ClampToBorder), inside);
+    }
+
+    #[test]
+    fn uses_the_border_outside() {
+        let mut sampler = Sampler::new(Filter::Nearest, Wrap::ClampToBorder);
+        sampler.border = [255, 255, 255, 255];
+        let texture = row(&[0.5, 0.5]);
+        assert_gray(sampler.sample(&texture, 0.5, 0.5), 0.5);
+        assert_gray(sampler.sample(&texture, -0.1, 0.5), 1.0);
+        assert_gray(sampler.sample(&texture, 0.5, 1.1), 1.0);
+        // but only for the wrap mode of that direction
+        sampler.wrap_v = Wrap::Repeat;
+        assert_gray(sampler.sample(&texture, 0.5, 1.1), 0.5);
+    }
+
+    #[test]
+    fn filters_nearest_and_bilinear() {
+        let texture = row(&[0.0, 1.0]);
+        let nearest = Sampler::new(Filter::Nearest, Wrap::ClampToEdge);
+        assert_gray(nearest.sample(&texture, 0.49, 0.5), 0.0);
+        assert_gray(nearest.sample(&texture, 0.51, 0.5), 1.0);
+
+        // texel centers are at u 0.25 and 0.75
+        let bilinear = Sampler::new(Filter::Bilinear, Wrap::ClampToEdge);
+        assert_gray(bilinear.sample(&texture, 0.25, 0.5), 0.0);
+        assert_gray(bilinear.sample(&texture, 0.375, 0.5), 0.25);
+        assert_gray(bilinear.sample(&texture, 0.5, 0.5), 0.5);
+        assert_gray(bilinear.sample(&texture, 0.0, 0.5), 0.0);
+        // blending with the texel on the other side when repeating
+        let repeat = Sampler::new(Filter::Bilinear, Wrap::Repeat);
+        assert_gray(repeat.sample(&texture, 0.0, 0.5), 0.5);
+    }
+
+    #[test]
+    fn counts_v_from_the_bottom() {
+        // the top row is white
+        let mut texture: Image<HdrColor> = Image::filled(1, 2, [0.0; 3]);
+        texture.pixels[0] = [1.0; 3];
+        let nearest = Sampler::new(Filter::Nearest, Wrap::ClampToEdge);
+        assert_gray(nearest.sample(&texture, 0.5, 0.75), 1.0);
+        assert_gray(nearest.sample(&texture, 0.5, 0.25), 0.0);
+    }
+
+    #[test]
+    fn filters_in_linear_light() {
+        let mut texture: Image = Image::filled(2, 1, [0, 0, 0]);
+        texture.pixels[1] = [255, 255, 255];
+        let bilinear = Sampler::new(Filter::Bilinear, Wrap::ClampToEdge);
+        assert_gray(bilinear.sample(&texture, 0.5, 0.5), 0.5);
+    }
+
+    #[test]
+    fn picks_levels_by_gradient() {
+        // a checkerboard, whose smaller levels are all gray
+        let mut texture: Image<HdrColor> = Image::filled(4, 4, [0.0; 3]);
+        for (i, pixel) in texture.pixels.iter_mut().enumerate() {
+            *pixel = [((i % 4 + i / 4) % 2) as f32; 3];
+        }
+        let mips = MipChain::new(&texture);
+        // at the top left texel, which is black, a pixel step of `dx` and `dy` texels
+        let grad = |sampler: &Sampler, dx: f64, dy: f64| {
+            let (duv_dx, duv_dy) = (
+                Vec2f {
+                    x: dx / 4.0,
+                    y: 0.0,
+                },
+                Vec2f {
+                    x: 0.0,
+                    y: dy / 4.0,
+                },
+            );
+            sampler.sample_grad(&mips, 0.125, 0.875, duv_dx, duv_dy)
+        };
+        let mut sampler = Sampler::new(Filter::Nearest, Wrap::Repeat);
+        sampler.mip_filter = MipFilter::Nearest;
+        assert_gray(grad(&sampler, 1.0, 1.0), 0.0);
+        assert_gray(grad(&sampler, 0.1, 0.1), 0.0);
+        assert_gray(grad(&sampler, 0.0, 0.0), 0.0);
+        assert_gray(grad(&sampler, 2.0, 2.0), 0.5);
+        assert_gray(grad(&sampler, 100.0, 100.0), 0.5);
+        // the larger of the two steps decides
+        assert_gray(grad(&sampler, 1.0, 2.0), 0.5);
+
+        // and between levels, they are blended
+        sampler.mip_filter = MipFilter::Linear;
+        assert_gray(grad(&sampler, 2f64.sqrt(), 2f64.sqrt()), 0.25);
+        sampler.mip_filter = MipFilter::None;
+        assert_gray(grad(&sampler, 100.0, 100.0), 0.0);
+    }
+}
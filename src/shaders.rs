@@ -17,43 +17,189 @@
 //!
 
 use crate::geometry::Matrix;
+use crate::geometry::Vec2f;
 use crate::geometry::Vec3f;
 use crate::image::Color;
+use crate::image::ColorSpace;
 use crate::image::Image;
+use crate::image::Pixel;
+use crate::image::Rgbaf;
+use crate::mipmap::MipChain;
 use crate::model::Model;
+use crate::mtl::Material;
 use crate::render::Shader;
+use crate::sampler::Sampler;
 
 /// A classic smooth shader!
 /// https://en.wikipedia.org/wiki/Gouraud_shading
-pub struct GouraudShader<'a> {
+///
+/// The diffuse texture may have alpha, which is passed on for blending.
+/// Models with vertex colors can be drawn with those instead of the texture.
+pub struct GouraudShader<'a, P = Color> {
     model: &'a Model,
     screen_transform: &'a Matrix,
-    diffuse_texture: &'a Image,
+    diffuse_texture: &'a Image<P>,
+    diffuse_mips: Option<&'a MipChain<P>>,
+    materials: &'a [MaterialBinding<'a, P>],
+    face: usize,
+    sampler: Sampler,
+    alpha_cutoff: u8,
+    /// the color space of the model's vertex colors, when drawing with them
+    vertex_colors: Option<ColorSpace>,
     light: Vec3f,
     varying_intensity: Vec3f,
+    varying_color: [Rgbaf; 3],
     varying_uv: Matrix,
 }
 
-impl<'a> GouraudShader<'a> {
+impl<'a, P: Pixel> GouraudShader<'a, P> {
     pub fn new(
         model: &'a Model,
         screen_transform: &'a Matrix,
-        diffuse_texture: &'a Image,
+        diffuse_texture: &'a Image<P>,
         light: Vec3f,
-    ) -> GouraudShader<'a> {
+    ) -> GouraudShader<'a, P> {
         GouraudShader {
             model,
             screen_transform,
             diffuse_texture,
+            diffuse_mips: None,
+            materials: &[],
+            face: 0,
+            sampler: Sampler::default(),
+            alpha_cutoff: 0,
+            vertex_colors: None,
             light,
             varying_intensity: Vec3f::zero(),
+            varying_color: [[0.0; 4]; 3],
             varying_uv: Matrix::new(2, 3),
         }
     }
+
+    /// Use the given sampler for the diffuse texture, instead of the default.
+    pub fn with_sampler(mut self, sampler: Sampler) -> GouraudShader<'a, P> {
+        self.sampler = sampler;
+        self
+    }
+
+    /// Sample the diffuse texture from its mipmaps, picking levels by how
+    /// fast the uv coords change from pixel to pixel.
+    pub fn with_mipmaps(mut self, diffuse_mips: &'a MipChain<P>) -> GouraudShader<'a, P> {
+        self.diffuse_mips = Some(diffuse_mips);
+        self
+    }
+
+    /// Draw faces with the bound materials, indexed like the model's materials.
+    /// Faces without a material, or whose material is not bound,
+    /// use the shader's diffuse texture as it is.
+    pub fn with_materials(
+        mut self,
+        materials: &'a [MaterialBinding<'a, P>],
+    ) -> GouraudShader<'a, P> {
+        self.materials = materials;
+        self
+    }
+
+    /// Interpolate the model's vertex colors for the diffuse color,
+    /// instead of sampling the texture, for models that have them.
+    /// The colors are decoded from the given color space, sRGB for most models.
+    pub fn with_vertex_colors(mut self, color_space: ColorSpace) -> GouraudShader<'a, P> {
+        self.vertex_colors = Some(color_space);
+        self
+    }
+
+    /// Discard fragments whose diffuse alpha is below `cutoff`,
+    /// for cut-outs like leaves and fences that can still write depth.
+    pub fn with_alpha_cutoff(mut self, cutoff: u8) -> GouraudShader<'a, P> {
+        self.alpha_cutoff = cutoff;
+        self
+    }
+
+    /// The diffuse color at `coords`, in linear light, from the material of
+    /// the face being drawn, sampling mipmaps if we know how `coords` change
+    /// from pixel to pixel in x and y.
+    fn diffuse(&self, coords: Vec3f, dcoords: Option<(Vec3f, Vec3f)>) -> Rgbaf {
+        let uv = m_mul_v(&self.varying_uv, coords);
+        let binding = self
+            .model
+            .face(self.face)
+            .material
+            .and_then(|m| self.materials.get(m));
+        let mips = binding.and_then(|b| b.diffuse).or(self.diffuse_mips);
+        let mut diffuse = if self.vertex_colors.is_some() && self.model.has_colors() {
+            // colors are interpolated in linear light, like texels are filtered
+            let mut color = [0.0; 4];
+            let weights = [coords.x, coords.y, coords.z];
+            for (corner, weight) in self.varying_color.iter().zip(weights.iter()) {
+                for (c, v) in color.iter_mut().zip(corner.iter()) {
+                    *c += v * weight;
+                }
+            }
+            color
+        } else {
+            match mips {
+                Some(mips) => self.sample_mips(mips, uv, dcoords),
+                None => self.sampler.sample(self.diffuse_texture, uv.x, uv.y),
+            }
+        };
+        if let Some(binding) = binding {
+            for (c, k) in diffuse.iter_mut().zip(binding.material.diffuse.iter()) {
+                *c *= k;
+            }
+            diffuse[3] *= binding.material.dissolve;
+            if let Some(mips) = binding.dissolve {
+                diffuse[3] *= self.sample_mips(mips, uv, dcoords)[0];
+            }
+        }
+        diffuse
+    }
+
+    fn sample_mips(&self, mips: &MipChain<P>, uv: Vec3f, dcoords: Option<(Vec3f, Vec3f)>) -> Rgbaf {
+        match dcoords {
+            Some((dcoords_dx, dcoords_dy)) => {
+                // uv is linear in the barycentric coords, so its derivatives are too
+                let duv_dx = m_mul_v(&self.varying_uv, dcoords_dx);
+                let duv_dy = m_mul_v(&self.varying_uv, dcoords_dy);
+                self.sampler.sample_grad(
+                    mips,
+                    uv.x,
+                    uv.y,
+                    Vec2f {
+                        x: duv_dx.x,
+                        y: duv_dx.y,
+                    },
+                    Vec2f {
+                        x: duv_dy.x,
+                        y: duv_dy.y,
+                    },
+                )
+            }
+            None => self.sampler.sample(mips.level(0), uv.x, uv.y),
+        }
+    }
+
+    /// Light the diffuse color, or `None` if the fragment should be discarded.
+    /// The result is not clamped, lights brighter than 1.0 can go past white.
+    fn shade(&self, coords: Vec3f, diffuse: Rgbaf) -> Option<Rgbaf> {
+        if (diffuse[3] * 255.0).round() < self.alpha_cutoff as f64 {
+            return None;
+        }
+        let mut color = diffuse;
+        let intensity = self.varying_intensity * coords;
+        for c in &mut color[..3] {
+            *c *= intensity;
+        }
+        Some(color)
+    }
 }
 
-impl Shader for GouraudShader<'_> {
+impl<P: Pixel> Shader for GouraudShader<'_, P> {
     fn vertex(&mut self, face_i: usize, vert_i: usize) -> Vec3f {
+        self.face = face_i;
+        if let (Some(space), Some(color)) = (self.vertex_colors, self.model.fcolor(face_i, vert_i))
+        {
+            self.varying_color[vert_i] = space.to_linear(color.to_rgbaf());
+        }
         let intensity = self.model.fnorm(face_i, vert_i) * self.light;
         m_put_col(&mut self.varying_uv, vert_i, self.model.fuv(face_i, vert_i));
         self.varying_intensity[vert_i] = intensity.max(0.0);
@@ -62,15 +208,41 @@ impl Shader for GouraudShader<'_> {
         Vec3f::from_m(&transformed)
     }
     fn fragment(&mut self, coords: Vec3f, color: &mut Color) -> bool {
-        let intensity = self.varying_intensity * coords;
-        let uv = m_mul_v(&self.varying_uv, coords);
-        let mut diffuse = self.diffuse_texture.get_unit(uv.x, uv.y);
-        for c in &mut diffuse {
-            *c = (*c as f64 * intensity) as u8
+        let diffuse = self.diffuse(coords, None);
+        match self.shade(coords, diffuse) {
+            Some(shaded) => {
+                *color = Color::from_rgbaf(shaded);
+                true // render fragment
+            }
+            None => false,
         }
-        *color = diffuse;
-        true // render fragment
     }
+    fn fragment_with_derivatives(
+        &mut self,
+        coords: Vec3f,
+        dcoords_dx: Vec3f,
+        dcoords_dy: Vec3f,
+        color: &mut Rgbaf,
+    ) -> bool {
+        let diffuse = self.diffuse(coords, Some((dcoords_dx, dcoords_dy)));
+        match self.shade(coords, diffuse) {
+            Some(shaded) => {
+                *color = shaded;
+                true // render fragment
+            }
+            None => false,
+        }
+    }
+}
+
+/// The textures a material is drawn with, the material's colors multiply them.
+pub struct MaterialBinding<'a, P = Color> {
+    pub material: &'a Material,
+    /// from the material's `map_Kd`, or `None` to use the shader's diffuse texture
+    pub diffuse: Option<&'a MipChain<P>>,
+    /// from the material's `map_d`, its red channel multiplies the alpha,
+    /// so tag it `ColorSpace::Linear` since its values are not colors
+    pub dissolve: Option<&'a MipChain<P>>,
 }
 
 fn m_put_col(m: &mut Matrix, c: usize, v: Vec3f) {
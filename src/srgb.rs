@@ -0,0 +1,84 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! The [sRGB] transfer function, how displays and most image files
+//! encode brightness, with more steps for dark colors than for bright ones.
+//!
+//! [sRGB]: https://en.wikipedia.org/wiki/SRGB
+//!
+
+/// Encode a linear light value in 0.0..=1.0 as sRGB.
+pub fn encode(linear: f64) -> f64 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Decode an sRGB value in 0.0..=1.0 to linear light.
+pub fn decode(encoded: f64) -> f64 {
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn keeps_black_and_white() {
+        assert!(close(encode(0.0), 0.0) && close(encode(1.0), 1.0));
+        assert!(close(decode(0.0), 0.0) && close(decode(1.0), 1.0));
+        // middle gray is a lot brighter encoded
+        assert!((encode(0.5) - 0.735_4).abs() < 1e-4);
+        assert!((decode(0.5) - 0.214_0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn is_continuous_where_the_curve_starts() {
+        // the straight part near black meets the power curve
+        let (linear, encoded) = (0.003_130_8, 0.040_45);
+        let power = 1.055 * f64::powf(linear, 1.0 / 2.4) - 0.055;
+        assert!((linear * 12.92 - power).abs() < 1e-6);
+        assert!((encode(linear) - encoded).abs() < 1e-6);
+        assert!((decode(encoded) - linear).abs() < 1e-6);
+    }
+
+    #[test]
+    fn round_trips() {
+        for i in 0..=1000 {
+            let x = i as f64 / 1000.0;
+            assert!(close(decode(encode(x)), x), "{}", x);
+            assert!(close(encode(decode(x)), x), "{}", x);
+            if i > 0 {
+                let before = (i - 1) as f64 / 1000.0;
+                assert!(encode(x) > encode(before) && decode(x) > decode(before));
+            }
+        }
+        // and so does every byte
+        for b in 0..=255u8 {
+            let encoded = encode(decode(b as f64 / 255.0));
+            assert_eq!((encoded * 255.0).round() as u8, b);
+        }
+    }
+}
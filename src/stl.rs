@@ -0,0 +1,479 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Reading and writing [STL] files, the triangle soups CAD programs export.
+//!
+//! [STL]: https://en.wikipedia.org/wiki/STL_(file_format)
+//!
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+
+use crate::geometry::Vec3;
+use crate::geometry::Vec3f;
+use crate::model::Face;
+use crate::model::Model;
+use crate::model::NormalMode;
+
+/// How close vertices must be to be welded by `Model::from_stl`.
+pub const DEFAULT_WELD_TOLERANCE: f64 = 1e-6;
+
+/// A triangle in an STL file, its normal and its corners.
+type Facet = (Vec3f, [Vec3f; 3]);
+
+impl Model {
+    /// Parse the given STL data, ASCII or binary, into a model,
+    /// welding vertices closer than `DEFAULT_WELD_TOLERANCE`.
+    pub fn from_stl<R: Read>(data: &mut R) -> Result<Model, StlError> {
+        Model::from_stl_with_tolerance(data, DEFAULT_WELD_TOLERANCE)
+    }
+
+    /// Parse the given STL data, ASCII or binary, into a model.
+    ///
+    /// STL files repeat the corners of every triangle, vertices closer than
+    /// `tolerance` to one another are welded into one, so that faces share them
+    /// and `generate_normals` can smooth across them. Faces get the normals of
+    /// their facets, or flat generated ones where the file has zero normals.
+    /// The model has no uvs, materials, or parts.
+    pub fn from_stl_with_tolerance<R: Read>(
+        data: &mut R,
+        tolerance: f64,
+    ) -> Result<Model, StlError> {
+        let mut bytes = Vec::new();
+        data.read_to_end(&mut bytes).map_err(StlError::Io)?;
+        let facets = if is_binary(&bytes) {
+            binary_facets(&bytes)?
+        } else {
+            ascii_facets(&String::from_utf8_lossy(&bytes))?
+        };
+
+        let mut welder = Welder::new(tolerance);
+        let mut norms = Vec::new();
+        let mut faces = Vec::with_capacity(facets.len());
+        for (normal, [a, b, c]) in facets {
+            let face_norms = if normal.norm() > 0.0 {
+                norms.push(normal.normalized());
+                let i = norms.len() - 1;
+                Some(Vec3::new(i, i, i))
+            } else {
+                None
+            };
+            faces.push(Face {
+                verts: Vec3::new(welder.weld(a), welder.weld(b), welder.weld(c)),
+                uvs: None,
+                norms: face_norms,
+                material: None,
+                smoothing_group: 0,
+            });
+        }
+        Ok(Model::from_faces(
+            welder.verts,
+            Vec::new(),
+            norms,
+            faces,
+            NormalMode::Flat,
+        ))
+    }
+
+    /// Save the model as a binary STL file, each face with the normal of its plane.
+    /// STL only has positions, uvs, normals, materials, and parts are dropped.
+    pub fn write_stl<W: Write>(&self, writer: &mut BufWriter<W>) -> io::Result<()> {
+        // binary files must not start with "solid", or they look like ASCII ones
+        let mut header = [0u8; 80];
+        let title = b"binary STL written by tinyrenderer";
+        header[..title.len()].copy_from_slice(title);
+        writer.write_all(&header)?;
+        writer.write_all(&(self.nfaces() as u32).to_le_bytes())?;
+        for f in 0..self.nfaces() {
+            let (a, b, c) = (self.fvert(f, 0), self.fvert(f, 1), self.fvert(f, 2));
+            let cross = (b - a).cross(c - a);
+            let normal = if cross.norm() > 0.0 {
+                cross.normalized()
+            } else {
+                cross
+            };
+            for v in [normal, a, b, c].iter() {
+                for i in 0..3 {
+                    writer.write_all(&(v[i] as f32).to_le_bytes())?;
+                }
+            }
+            // the attribute byte count, which nothing agrees on the meaning of
+            writer.write_all(&[0, 0])?;
+        }
+        writer.flush()
+    }
+}
+
+/// Why STL data could not be parsed.
+#[derive(Debug)]
+pub enum StlError {
+    /// the data could not be read
+    Io(io::Error),
+    /// binary data ends before all the triangles its header promises
+    Truncated,
+    /// ASCII data has something unexpected on the line, counting from 1
+    Unexpected {
+        line: usize,
+        expected: &'static str,
+        found: String,
+    },
+}
+
+impl fmt::Display for StlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StlError::Io(e) => write!(f, "cannot read STL data: {}", e),
+            StlError::Truncated => write!(f, "truncated STL data"),
+            StlError::Unexpected {
+                line,
+                expected,
+                found,
+            } => write!(f, "{}: expected {}, found {:?}", line, expected, found),
+        }
+    }
+}
+
+impl error::Error for StlError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            StlError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Is the data binary STL? ASCII files start with "solid", but so do the
+/// headers of some binary files, so those are told apart by their size.
+fn is_binary(bytes: &[u8]) -> bool {
+    let text = bytes.iter().skip_while(|b| b.is_ascii_whitespace());
+    if !text.take(5).eq(b"solid".iter()) {
+        return true;
+    }
+    bytes.len() >= 84 && 84 + 50 * triangle_count(bytes) as u64 == bytes.len() as u64
+}
+
+fn triangle_count(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]])
+}
+
+/// Binary STL: an 80 byte header, a triangle count, and 50 bytes per triangle.
+fn binary_facets(bytes: &[u8]) -> Result<Vec<Facet>, StlError> {
+    if bytes.len() < 84 {
+        return Err(StlError::Truncated);
+    }
+    let count = triangle_count(bytes) as usize;
+    let triangles = bytes[84..].chunks_exact(50);
+    if triangles.len() < count {
+        return Err(StlError::Truncated);
+    }
+    let vector = |b: &[u8]| {
+        let float = |i: usize| f32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]) as f64;
+        Vec3f::new(float(0), float(4), float(8))
+    };
+    Ok(triangles
+        .take(count)
+        .map(|t| {
+            let corners = [vector(&t[12..]), vector(&t[24..]), vector(&t[36..])];
+            (vector(t), corners)
+        })
+        .collect())
+}
+
+/// ASCII STL: any number of `solid`s, with `facet`s of three `vertex`es each.
+fn ascii_facets(text: &str) -> Result<Vec<Facet>, StlError> {
+    let mut tokens = AsciiTokens {
+        tokens: text
+            .lines()
+            .enumerate()
+            .flat_map(|(i, line)| line.split_whitespace().map(move |t| (i + 1, t)))
+            .peekable(),
+        line: 1,
+    };
+    let mut facets = Vec::new();
+    while tokens.peek().is_some() {
+        // the name is the rest of the line
+        let solid_line = tokens.expect("solid")?;
+        tokens.skip_line(solid_line);
+        loop {
+            match tokens.next() {
+                Some("facet") => {
+                    tokens.expect("normal")?;
+                    let normal = tokens.vector()?;
+                    tokens.expect("outer")?;
+                    tokens.expect("loop")?;
+                    let mut corners = [Vec3f::zero(); 3];
+                    for corner in &mut corners {
+                        tokens.expect("vertex")?;
+                        *corner = tokens.vector()?;
+                    }
+                    tokens.expect("endloop")?;
+                    tokens.expect("endfacet")?;
+                    facets.push((normal, corners));
+                }
+                Some("endsolid") => {
+                    let line = tokens.line;
+                    tokens.skip_line(line);
+                    break;
+                }
+                found => return Err(tokens.unexpected("facet or endsolid", found)),
+            }
+        }
+    }
+    Ok(facets)
+}
+
+/// The whitespace separated tokens of ASCII STL, with their line numbers.
+struct AsciiTokens<'a, I: Iterator<Item = (usize, &'a str)>> {
+    tokens: std::iter::Peekable<I>,
+    /// the line of the last token
+    line: usize,
+}
+
+impl<'a, I: Iterator<Item = (usize, &'a str)>> AsciiTokens<'a, I> {
+    fn next(&mut self) -> Option<&'a str> {
+        let (line, token) = self.tokens.next()?;
+        self.line = line;
+        Some(token)
+    }
+
+    fn peek(&mut self) -> Option<&'a str> {
+        self.tokens.peek().map(|&(_, token)| token)
+    }
+
+    /// skip the rest of the tokens on the line
+    fn skip_line(&mut self, line: usize) {
+        while self.tokens.peek().map_or(false, |&(l, _)| l == line) {
+            self.tokens.next();
+        }
+    }
+
+    /// the next token, which must be `keyword`, returning its line
+    fn expect(&mut self, keyword: &'static str) -> Result<usize, StlError> {
+        match self.next() {
+            Some(token) if token == keyword => Ok(self.line),
+            found => Err(self.unexpected(keyword, found)),
+        }
+    }
+
+    fn vector(&mut self) -> Result<Vec3f, StlError> {
+        let mut v = Vec3f::zero();
+        for i in 0..3 {
+            v[i] = match self.next() {
+                Some(token) => token
+                    .parse()
+                    .map_err(|_| self.unexpected("a number", Some(token)))?,
+                None => return Err(self.unexpected("a number", None)),
+            };
+        }
+        Ok(v)
+    }
+
+    fn unexpected(&self, expected: &'static str, found: Option<&str>) -> StlError {
+        StlError::Unexpected {
+            line: self.line,
+            expected,
+            found: found.unwrap_or("the end of the data").to_owned(),
+        }
+    }
+}
+
+/// Merges vertices closer than the tolerance, looking for them in a grid
+/// of cells as big as the tolerance, so only neighboring cells need looking in.
+struct Welder {
+    tolerance: f64,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+    verts: Vec<Vec3f>,
+}
+
+impl Welder {
+    fn new(tolerance: f64) -> Welder {
+        Welder {
+            tolerance,
+            cells: HashMap::new(),
+            verts: Vec::new(),
+        }
+    }
+
+    /// The index of the vertex `p` is welded to, adding it if there is none.
+    fn weld(&mut self, p: Vec3f) -> usize {
+        let cell = if self.tolerance > 0.0 {
+            let cell = |x: f64| (x / self.tolerance).floor() as i64;
+            [cell(p.x), cell(p.y), cell(p.z)]
+        } else {
+            // only identical vertices are welded
+            [
+                p.x.to_bits() as i64,
+                p.y.to_bits() as i64,
+                p.z.to_bits() as i64,
+            ]
+        };
+        let reach = if self.tolerance > 0.0 { 1 } else { 0 };
+        for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                for dz in -reach..=reach {
+                    // huge and infinite coordinates are in the cells at the ends
+                    let neighbor = [
+                        cell[0].saturating_add(dx),
+                        cell[1].saturating_add(dy),
+                        cell[2].saturating_add(dz),
+                    ];
+                    let found = self.cells.get(&neighbor).and_then(|indices| {
+                        indices
+                            .iter()
+                            .find(|&&i| (self.verts[i] - p).norm() <= self.tolerance)
+                    });
+                    if let Some(&i) = found {
+                        return i;
+                    }
+                }
+            }
+        }
+        self.verts.push(p);
+        let i = self.verts.len() - 1;
+        self.cells.entry(cell).or_default().push(i);
+        i
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a unit square in two facets, the second with a zero normal
+    const SQUARE: &str = "solid square
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 1 1 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid square
+";
+
+    /// binary STL of facets of a normal and three corners each
+    fn binary(header: &[u8], facets: &[[f32; 12]]) -> Vec<u8> {
+        let mut bytes = header.to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&(facets.len() as u32).to_le_bytes());
+        for facet in facets {
+            for x in facet.iter() {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0, 0]);
+        }
+        bytes
+    }
+
+    fn nverts(model: &Model) -> usize {
+        model
+            .faces()
+            .flat_map(|f| (0..3).map(move |v| f.verts[v] + 1))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn assert_square(model: &Model) {
+        assert_eq!(model.nfaces(), 2);
+        // the diagonal's corners are shared
+        assert_eq!(nverts(model), 4);
+        let c = model.fvert(1, 2);
+        assert_eq!((c.x, c.y, c.z), (0.0, 1.0, 0.0));
+        for f in 0..2 {
+            let n = model.fnorm(f, 0);
+            assert_eq!((n.x, n.y, n.z), (0.0, 0.0, 1.0), "face {}", f);
+        }
+    }
+
+    #[test]
+    fn reads_ascii() {
+        assert_square(&Model::from_stl(&mut SQUARE.as_bytes()).unwrap());
+        let missing = SQUARE.replace("endloop", "");
+        match Model::from_stl(&mut missing.as_bytes()) {
+            Err(StlError::Unexpected { line: 8, .. }) => {}
+            other => panic!("expected an error on line 8, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn reads_binary() {
+        let facets = [
+            [0., 0., 1., 0., 0., 0., 1., 0., 0., 1., 1., 0.],
+            [0., 0., 0., 0., 0., 0., 1., 1., 0., 0., 1., 0.],
+        ];
+        // even with a header that looks like ASCII
+        for header in &[&b"binary"[..], &b"solid square"[..]] {
+            let bytes = binary(header, &facets);
+            assert_square(&Model::from_stl(&mut bytes.as_slice()).unwrap());
+        }
+        let bytes = binary(b"binary", &facets);
+        match Model::from_stl(&mut &bytes[..bytes.len() - 1]) {
+            Err(StlError::Truncated) => {}
+            other => panic!("expected truncated data, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_write() {
+        let model = Model::from_stl(&mut SQUARE.as_bytes()).unwrap();
+        let mut writer = BufWriter::new(Vec::new());
+        model.write_stl(&mut writer).unwrap();
+        let bytes = writer.into_inner().unwrap();
+        assert_eq!(bytes.len(), 84 + 50 * 2);
+        assert_square(&Model::from_stl(&mut bytes.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn welds_within_tolerance() {
+        // the second triangle's corners on the shared edge are off by 1e-4
+        let facets = [
+            [0., 0., 1., 0., 0., 0., 1., 0., 0., 1., 1., 0.],
+            [0., 0., 1., 1e-4, 0., 0., 1., 1., 0., 0., 1., 0.],
+        ];
+        let bytes = binary(b"binary", &facets);
+        let welded = |tolerance| {
+            let model = Model::from_stl_with_tolerance(&mut bytes.as_slice(), tolerance).unwrap();
+            nverts(&model)
+        };
+        assert_eq!(welded(1e-3), 4);
+        assert_eq!(welded(1e-5), 5);
+        assert_eq!(welded(0.0), 5);
+        assert_eq!(welded(DEFAULT_WELD_TOLERANCE), 5);
+    }
+
+    #[test]
+    fn welds_extreme_coordinates() {
+        let (max, inf) = (f32::MAX, f32::INFINITY);
+        let facets = [
+            [0., 0., 1., max, max, max, -max, -max, -max, inf, -inf, 0.],
+            [0., 0., 1., max, max, max, -max, -max, -max, inf, -inf, 0.],
+        ];
+        let bytes = binary(b"binary", &facets);
+        let model = Model::from_stl(&mut bytes.as_slice()).unwrap();
+        assert_eq!(model.nfaces(), 2);
+    }
+}
@@ -17,68 +17,240 @@
 //! but wanted to try to have no deps outside of std.
 //!
 
-use std::io::{BufReader, Read};
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
 
+use crate::image::ColorSpace;
 use crate::image::Image;
+use crate::image::Pixel;
+use crate::image::Rgba;
 
-impl Image {
+impl<P: Pixel> Image<P> {
     /// Parse the given TGA data into a bitmap image.
-    pub fn from_tga<R: Read>(data: &mut R) -> Image {
-        let header = TgaHeader::from_data(data);
-        // only 24 bit RLE is implemented, origins from the left
-        assert_eq!(header.bit_depth, 24);
-        assert_eq!(header.image_type, 10);
-        assert!(header.origin == 0b00 || header.origin == 0b10);
-
-        let pixel_count = header.image_width * header.image_height;
-        let flipped = header.origin == 0b00;
-        let pixels = read_image_data_rle(data, pixel_count, header.image_width, flipped);
-        Image {
-            width: header.image_width,
-            height: header.image_height,
-            flipped,
+    /// Panics if the data is not a supported TGA image, see `try_from_tga`.
+    pub fn from_tga<R: Read>(data: &mut R) -> Image<P> {
+        Image::try_from_tga(data).unwrap_or_else(|e| panic!("cannot parse TGA data: {}", e))
+    }
+
+    /// Parse the given TGA data into a bitmap image,
+    /// reporting why if it is not a TGA image we can read.
+    ///
+    /// Uncompressed and RLE true-color (15, 16, 24, or 32 bit),
+    /// grayscale (8 or 16 bit), and color-mapped (8 or 16 bit indices)
+    /// images are supported, with any origin.
+    /// Color-mapped images are expanded to true color.
+    /// Images whose header says they have alpha bits keep their alpha,
+    /// if the pixels have room for it, other images are fully opaque.
+    /// The image is tagged as sRGB.
+    pub fn try_from_tga<R: Read>(data: &mut R) -> Result<Image<P>, TgaError> {
+        let header = TgaHeader::from_data(data)?;
+        let format = PixelFormat::from_header(&header)?;
+
+        let mut reader = BufReader::new(data);
+        // we have no use for the image ID, but it sits between the header and the pixels
+        let mut image_id = vec![0; header.id_length as usize];
+        read_or(&mut reader, &mut image_id, TgaError::TruncatedPixelData)?;
+        // true-color and grayscale images may have a color map too, which we skip
+        let palette = Palette::from_data(&mut reader, &header)?;
+
+        let (width, height) = (header.image_width, header.image_height);
+        // the header can say the image is huge, so only make room for the rows that are there
+        let mut pixels = Vec::new();
+        let mut row_data = vec![0u8; width * format.bytes_per_pixel()];
+        for r in 0..height {
+            if header.is_rle() {
+                read_pixel_row_rle(&mut reader, r, format.bytes_per_pixel(), &mut row_data)?;
+            } else {
+                read_or(&mut reader, &mut row_data, TgaError::TruncatedPixelData)?;
+            }
+
+            let start = pixels.len();
+            for bytes in row_data.chunks_exact(format.bytes_per_pixel()) {
+                pixels.push(P::from_rgba(format.decode(
+                    bytes,
+                    &palette,
+                    header.has_alpha(),
+                )?));
+            }
+            // we always keep pixels left to right
+            if header.origin_right() {
+                pixels[start..].reverse();
+            }
+        }
+        // and the top row first
+        if header.origin_bottom() {
+            for r in 0..height / 2 {
+                let (top, bottom) = pixels.split_at_mut((height - r - 1) * width);
+                top[r * width..(r + 1) * width].swap_with_slice(&mut bottom[..width]);
+            }
+        }
+
+        Ok(Image {
+            width,
+            height,
+            // remember if rows were counted from the bottom in the file
+            flipped: header.origin_bottom(),
+            // TGA has no say in it, but everything else is sRGB
+            color_space: ColorSpace::Srgb,
             pixels,
+        })
+    }
+}
+
+/// How pixel data is stored when writing TGA files.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TgaCompression {
+    /// raw pixels, image type 2
+    Uncompressed,
+    /// run-length encoded pixels, image type 10
+    Rle,
+}
+
+impl<P: Pixel> Image<P> {
+    /// Save the bitmap data as a 24 bit true-color TGA image,
+    /// or 32 bit with 8 alpha bits if the image has an alpha channel.
+    /// Flipped images are stored bottom row first, others top row first,
+    /// so that `from_tga` gives back the same image.
+    /// Images more than 65535 pixels wide or tall don't fit in a TGA header,
+    /// and fail with `InvalidInput` before anything is written.
+    pub fn write_tga<W: Write>(
+        &self,
+        writer: &mut BufWriter<W>,
+        compression: TgaCompression,
+    ) -> io::Result<()> {
+        let image_type = match compression {
+            TgaCompression::Uncompressed => 2,
+            TgaCompression::Rle => 10,
+        };
+        let origin = if self.flipped { 0b00 } else { 0b10 };
+        let too_big = || io::Error::new(io::ErrorKind::InvalidInput, "image too big for TGA");
+        let width = u16::try_from(self.width).map_err(|_| too_big())?;
+        let height = u16::try_from(self.height).map_err(|_| too_big())?;
+        let mut header = [0; 18];
+        header[2] = image_type;
+        header[12..14].copy_from_slice(&width.to_le_bytes());
+        header[14..16].copy_from_slice(&height.to_le_bytes());
+        header[16] = 8 * P::CHANNELS as u8;
+        let alpha_bits = if P::CHANNELS == 4 { 8 } else { 0 };
+        header[17] = origin << 4 | alpha_bits;
+        writer.write_all(&header)?;
+
+        for r in 0..self.height {
+            let i = if self.flipped { self.height - r - 1 } else { r };
+            let row = &self.pixels[i * self.width..(i + 1) * self.width];
+            match compression {
+                TgaCompression::Uncompressed => {
+                    for p in row {
+                        writer.write_all(&bgra(*p)[..P::CHANNELS])?;
+                    }
+                }
+                TgaCompression::Rle => write_pixel_row_rle(writer, row)?,
+            }
         }
+        Ok(())
     }
 }
 
+/// Why TGA data could not be decoded.
+#[derive(Debug)]
+pub enum TgaError {
+    /// the data could not be read, for other reasons than ending early
+    Io(io::Error),
+    /// the header ends before all its fields are read
+    TruncatedHeader,
+    /// the image type is not one we can decode
+    UnsupportedImageType(u8),
+    /// the bits per pixel are not one we can decode for this image type
+    UnsupportedBitDepth(u8),
+    /// the image is color-mapped, but there is no color map
+    MissingColormap,
+    /// the bits per color map entry are not one we can decode
+    UnsupportedColormapDepth(u8),
+    /// a pixel refers to a color not in the color map
+    ColormapIndexOutOfRange(usize),
+    /// the data ends before all pixels are read
+    TruncatedPixelData,
+    /// an RLE packet covers more pixels than are left in its row
+    RlePacketOverrun { row: usize },
+}
+
+impl fmt::Display for TgaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TgaError::Io(e) => write!(f, "cannot read TGA data: {}", e),
+            TgaError::TruncatedHeader => write!(f, "truncated header"),
+            TgaError::UnsupportedImageType(t) => write!(f, "unsupported image type {}", t),
+            TgaError::UnsupportedBitDepth(d) => write!(f, "unsupported bit depth {}", d),
+            TgaError::MissingColormap => write!(f, "color-mapped image without a color map"),
+            TgaError::UnsupportedColormapDepth(d) => {
+                write!(f, "unsupported color map entry depth {}", d)
+            }
+            TgaError::ColormapIndexOutOfRange(i) => {
+                write!(f, "color map index {} out of range", i)
+            }
+            TgaError::TruncatedPixelData => write!(f, "truncated pixel data"),
+            TgaError::RlePacketOverrun { row } => {
+                write!(f, "RLE packet runs past the end of row {}", row)
+            }
+        }
+    }
+}
+
+impl error::Error for TgaError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TgaError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Read exactly enough data to fill `buf`, mapping running out of data to `eof`.
+fn read_or<R: Read>(data: &mut R, buf: &mut [u8], eof: TgaError) -> Result<(), TgaError> {
+    data.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => eof,
+        _ => TgaError::Io(e),
+    })
+}
+
 struct TgaHeader {
+    id_length: u8,
+    colormap_type: u8,
     image_type: u8,
+    colormap_start: usize,
+    colormap_length: usize,
+    colormap_bits: u8,
     image_width: usize,
     image_height: usize,
     bit_depth: u8,
-    origin: u8, // but really 4
+    alpha_bits: u8, // but really 4
+    origin: u8,     // but really 2
 }
 
 impl TgaHeader {
     /// Parse the TGA header in the given data.
-    pub fn from_data<R: Read>(data: &mut R) -> TgaHeader {
-        // we'll just read the header fields in order
-        let mut id_length = [0];
-        let mut colormap_type = [0];
-        let mut image_type = [0];
-        let mut colormap_spec = [0, 0, 0, 0, 0];
-        let mut image_spec = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
-        data.read_exact(&mut id_length)
-            .expect("cannot read id length");
-        data.read_exact(&mut colormap_type)
-            .expect("cannot read colormap type");
-        data.read_exact(&mut image_type)
-            .expect("cannot read image type");
-        data.read_exact(&mut colormap_spec)
-            .expect("cannot read colormap spec");
-        data.read_exact(&mut image_spec)
-            .expect("cannot read image spec");
+    pub fn from_data<R: Read>(data: &mut R) -> Result<TgaHeader, TgaError> {
+        // the header is a fixed 18 bytes, we'll just read the fields in order
+        let mut header = [0; 18];
+        read_or(data, &mut header, TgaError::TruncatedHeader)?;
+        let id_length = &header[0..1];
+        let colormap_type = &header[1..2];
+        let image_type = &header[2..3];
+        let colormap_spec = &header[3..8];
+        let image_spec = &header[8..18];
 
         // not all fields are used in this code,
         // but they are named for clarity
-        let _id_length = id_length[0];
-        let _colormap_type = colormap_type[0];
+        let id_length = id_length[0];
+        let colormap_type = colormap_type[0];
         let image_type = image_type[0];
 
-        let _colormap_start = to_u16(&colormap_spec[0..2]);
-        let colormap_length = to_u16(&colormap_spec[2..4]);
-        let _colormap_bits = colormap_spec[4];
+        let colormap_start = to_u16(&colormap_spec[0..2]) as usize;
+        let colormap_length = to_u16(&colormap_spec[2..4]) as usize;
+        let colormap_bits = colormap_spec[4];
 
         let _origin_x = to_u16(&image_spec[0..2]);
         let _origin_y = to_u16(&image_spec[2..4]);
@@ -86,19 +258,184 @@ impl TgaHeader {
         let image_height = to_u16(&image_spec[6..8]) as usize;
         let bit_depth = image_spec[8];
         let image_desc = image_spec[9];
-        let _alpha_bits = image_desc & 0b0000_1111;
+        let alpha_bits = image_desc & 0b0000_1111;
         let origin = (image_desc & 0b0011_0000) >> 4;
 
-        assert_eq!(colormap_length, 0, "color maps are not supported");
-
-        TgaHeader {
+        Ok(TgaHeader {
+            id_length,
+            colormap_type,
             image_type,
+            colormap_start,
+            colormap_length,
+            colormap_bits,
             image_width,
             image_height,
             bit_depth,
+            alpha_bits,
             origin,
+        })
+    }
+
+    /// Is there a color map between the image ID and the pixel data?
+    fn has_colormap(&self) -> bool {
+        self.colormap_type == 1
+    }
+
+    /// Do the pixels (and color map entries) have alpha?
+    fn has_alpha(&self) -> bool {
+        self.alpha_bits != 0
+    }
+
+    /// Are the pixels indices into the color map? Image types 1 and 9.
+    fn is_color_mapped(&self) -> bool {
+        self.image_type & 0b0111 == 1
+    }
+
+    /// Image types 9, 10, and 11 are the RLE versions of 1, 2, and 3.
+    fn is_rle(&self) -> bool {
+        self.image_type & 0b1000 != 0
+    }
+
+    /// Is the first pixel row the bottom row?
+    fn origin_bottom(&self) -> bool {
+        self.origin & 0b10 == 0
+    }
+
+    /// Are the pixels in each row stored right to left?
+    fn origin_right(&self) -> bool {
+        self.origin & 0b01 != 0
+    }
+}
+
+/// The colors of a color map, and the index of the first one.
+struct Palette {
+    start: usize,
+    colors: Vec<Rgba>,
+}
+
+impl Palette {
+    fn empty() -> Palette {
+        Palette {
+            start: 0,
+            colors: Vec::new(),
+        }
+    }
+
+    /// Read the color map described by the header, if there is one.
+    /// Only color-mapped images use theirs, the color maps of other images
+    /// are skipped, whatever their entries are.
+    fn from_data<R: Read>(data: &mut R, header: &TgaHeader) -> Result<Palette, TgaError> {
+        if !header.has_colormap() {
+            return Ok(Palette::empty());
+        }
+        if !header.is_color_mapped() {
+            let entry_size = (header.colormap_bits as u64 + 7) / 8;
+            let length = header.colormap_length as u64 * entry_size;
+            let skipped =
+                io::copy(&mut data.take(length), &mut io::sink()).map_err(TgaError::Io)?;
+            if skipped != length {
+                return Err(TgaError::TruncatedPixelData);
+            }
+            return Ok(Palette::empty());
+        }
+        let format = match header.colormap_bits {
+            15 | 16 => PixelFormat::Bgr16,
+            24 => PixelFormat::Bgr24,
+            32 => PixelFormat::Bgra32,
+            d => return Err(TgaError::UnsupportedColormapDepth(d)),
+        };
+        let mut colormap = vec![0; header.colormap_length * format.bytes_per_pixel()];
+        read_or(data, &mut colormap, TgaError::TruncatedPixelData)?;
+        let colors = colormap
+            .chunks_exact(format.bytes_per_pixel())
+            .map(|bytes| format.decode(bytes, &Palette::empty(), header.has_alpha()))
+            .collect::<Result<_, _>>()?;
+        Ok(Palette {
+            start: header.colormap_start,
+            colors,
+        })
+    }
+
+    fn lookup(&self, index: usize) -> Result<Rgba, TgaError> {
+        index
+            .checked_sub(self.start)
+            .and_then(|i| self.colors.get(i))
+            .copied()
+            .ok_or(TgaError::ColormapIndexOutOfRange(index))
+    }
+}
+
+/// How the bytes of a single pixel are laid out.
+#[derive(Clone, Copy)]
+enum PixelFormat {
+    /// an index into the color map
+    Indexed8,
+    /// a 16 bit index into the color map, in Intel byte order
+    Indexed16,
+    Gray8,
+    /// gray followed by an alpha byte
+    Gray16,
+    /// packed ARRRRRGG GGGBBBBB, in Intel byte order
+    Bgr16,
+    Bgr24,
+    /// with an alpha byte
+    Bgra32,
+}
+
+impl PixelFormat {
+    fn from_header(header: &TgaHeader) -> Result<PixelFormat, TgaError> {
+        match (header.image_type & 0b0111, header.bit_depth) {
+            (1, _) if !header.has_colormap() => Err(TgaError::MissingColormap),
+            (1, 8) => Ok(PixelFormat::Indexed8),
+            (1, 16) => Ok(PixelFormat::Indexed16),
+            (2, 15) | (2, 16) => Ok(PixelFormat::Bgr16),
+            (2, 24) => Ok(PixelFormat::Bgr24),
+            (2, 32) => Ok(PixelFormat::Bgra32),
+            (3, 8) => Ok(PixelFormat::Gray8),
+            (3, 16) => Ok(PixelFormat::Gray16),
+            (1, d) | (2, d) | (3, d) => Err(TgaError::UnsupportedBitDepth(d)),
+            _ => Err(TgaError::UnsupportedImageType(header.image_type)),
         }
     }
+
+    fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Indexed8 | PixelFormat::Gray8 => 1,
+            PixelFormat::Indexed16 | PixelFormat::Gray16 | PixelFormat::Bgr16 => 2,
+            PixelFormat::Bgr24 => 3,
+            PixelFormat::Bgra32 => 4,
+        }
+    }
+
+    /// Turn the bytes for one pixel into an RGBA color,
+    /// looking it up in the palette if the pixel is an index.
+    /// Alpha bits are only used if `has_alpha`, as many files leave them unset.
+    fn decode(self, bytes: &[u8], palette: &Palette, has_alpha: bool) -> Result<Rgba, TgaError> {
+        let alpha = |a: u8| if has_alpha { a } else { 255 };
+        let color = match self {
+            PixelFormat::Indexed8 => palette.lookup(bytes[0] as usize)?,
+            PixelFormat::Indexed16 => palette.lookup(to_u16(bytes) as usize)?,
+            PixelFormat::Gray8 => [bytes[0], bytes[0], bytes[0], 255],
+            PixelFormat::Gray16 => [bytes[0], bytes[0], bytes[0], alpha(bytes[1])],
+            PixelFormat::Bgr16 => {
+                let packed = to_u16(bytes);
+                let a = if packed & 0x8000 != 0 { 255 } else { 0 };
+                let r = (packed >> 10) & 0b1_1111;
+                let g = (packed >> 5) & 0b1_1111;
+                let b = packed & 0b1_1111;
+                [expand_5bit(r), expand_5bit(g), expand_5bit(b), alpha(a)]
+            }
+            // "fix" BGR
+            PixelFormat::Bgr24 => [bytes[2], bytes[1], bytes[0], 255],
+            PixelFormat::Bgra32 => [bytes[2], bytes[1], bytes[0], alpha(bytes[3])],
+        };
+        Ok(color)
+    }
+}
+
+/// Scale a 5 bit color channel to 8 bits, so that 31 becomes 255.
+fn expand_5bit(c: u16) -> u8 {
+    ((c << 3) | (c >> 2)) as u8
 }
 
 /// TGA uses Intel byte ordering, we have to convert to Motorola.
@@ -106,63 +443,345 @@ fn to_u16(bytes: &[u8]) -> u16 {
     ((bytes[1] as u16) << 8) | bytes[0] as u16
 }
 
-fn read_image_data_rle<R: Read>(
-    data: &mut R,
-    count: usize,
-    width: usize,
-    upsidedown: bool,
-) -> Vec<[u8; 3]> {
-    let num_rows = count / width;
-    let mut reader = BufReader::new(data);
-    let mut pixels = vec![[0u8; 3]; count];
+/// TGA stores colors as BGR, followed by alpha if there is any.
+fn bgra<P: Pixel>(pixel: P) -> Rgba {
+    let [r, g, b, a] = pixel.to_rgba();
+    [b, g, r, a]
+}
 
-    for r in 0..num_rows {
-        let i = if upsidedown { num_rows - r - 1 } else { r };
-        let row_start = i * width;
-        let row_end = row_start + width;
-        read_pixel_row_rle(&mut reader, width, &mut pixels[row_start..row_end]);
+/// Encode a row as RLE packets, never letting a packet pass the end of the row.
+fn write_pixel_row_rle<W: Write, P: Pixel>(sink: &mut W, row: &[P]) -> io::Result<()> {
+    // packets hold at most 128 pixels
+    const MAX_PACKET: usize = 128;
+    let mut pos = 0;
+    while pos < row.len() {
+        let run = row[pos..]
+            .iter()
+            .take(MAX_PACKET)
+            .take_while(|p| **p == row[pos])
+            .count();
+        if run > 1 {
+            // RLE packet, with the size encoded as 1 less than the number of pixels
+            sink.write_all(&[0b1000_0000 | (run - 1) as u8])?;
+            sink.write_all(&bgra(row[pos])[..P::CHANNELS])?;
+            pos += run;
+        } else {
+            // raw packet, until the next run of repeated pixels starts
+            let mut end = pos + 1;
+            while end < row.len() && end - pos < MAX_PACKET && row[end] != row[end - 1] {
+                end += 1;
+            }
+            // the last pixel we looked at starts a run, leave it for the next packet
+            if end < row.len() && end - pos > 1 && row[end] == row[end - 1] {
+                end -= 1;
+            }
+            sink.write_all(&[(end - pos - 1) as u8])?;
+            for p in &row[pos..end] {
+                sink.write_all(&bgra(*p)[..P::CHANNELS])?;
+            }
+            pos = end;
+        }
     }
-
-    pixels
+    Ok(())
 }
 
-fn read_pixel_row_rle<R: Read>(source: &mut R, width: usize, row_buffer: &mut [[u8; 3]]) {
+/// Decode RLE packets until the row is full, `bpp` bytes for each pixel.
+fn read_pixel_row_rle<R: Read>(
+    source: &mut R,
+    row: usize,
+    bpp: usize,
+    row_buffer: &mut [u8],
+) -> Result<(), TgaError> {
     let mut pos = 0;
-    while pos < width {
-        // RLE packets should not pass the image width
-        pos += read_pixel_packet(source, &mut row_buffer[pos..]);
+    while pos < row_buffer.len() {
+        pos += bpp * read_pixel_packet(source, row, bpp, &mut row_buffer[pos..])?;
     }
+    Ok(())
 }
 
-fn read_pixel_packet<R: Read>(source: &mut R, sink: &mut [[u8; 3]]) -> usize {
+fn read_pixel_packet<R: Read>(
+    source: &mut R,
+    row: usize,
+    bpp: usize,
+    sink: &mut [u8],
+) -> Result<usize, TgaError> {
     let mut packet_header = [0];
-    let mut pixel_value = [0, 0, 0];
-    source
-        .read_exact(&mut packet_header)
-        .expect("unable to read RLE packet header");
+    read_or(source, &mut packet_header, TgaError::TruncatedPixelData)?;
     let packet_type = (packet_header[0] & 0b1000_0000) >> 7;
     // This 7 bit value is actually encoded as 1 less than the number
     // of pixels in the packet.
     let packet_size = (packet_header[0] & 0b0111_1111) as usize + 1;
+    // RLE packets should not pass the image width
+    if packet_size * bpp > sink.len() {
+        return Err(TgaError::RlePacketOverrun { row });
+    }
+    let sink = &mut sink[..packet_size * bpp];
     if packet_type == 1 {
         // 1 means RLE packet
-        source
-            .read_exact(&mut pixel_value)
-            .expect("unable to read RLE packet");
-        pixel_value.reverse(); // "fix" BGR
-        for i in 0..packet_size {
-            sink[i] = pixel_value;
+        let (pixel_value, rest) = sink.split_at_mut(bpp);
+        read_or(source, pixel_value, TgaError::TruncatedPixelData)?;
+        for pixel in rest.chunks_exact_mut(bpp) {
+            pixel.copy_from_slice(pixel_value);
         }
     } else {
         // 0 means raw packet
-        for i in 0..packet_size {
-            source
-                .read_exact(&mut pixel_value)
-                .expect("unable to read raw packet");
-            pixel_value.reverse(); // "fix" BGR
-            sink[i] = pixel_value;
+        read_or(source, sink, TgaError::TruncatedPixelData)?;
+    }
+
+    Ok(packet_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Color;
+
+    /// an image with every pixel different, and some runs for RLE
+    fn test_image<P: Pixel>(flipped: bool) -> Image<P> {
+        let (width, height) = (300, 5);
+        let mut image = Image::filled(width, height, P::default());
+        image.flipped = flipped;
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            let (x, y) = (i % width, i / width);
+            // a run in the middle of each row, longer than one RLE packet
+            let x = if (100..260).contains(&x) { 100 } else { x };
+            let rgba = [x as u8, (x * 7 + y) as u8, y as u8 * 40, (x + y) as u8];
+            *pixel = P::from_rgba(rgba);
+        }
+        image
+    }
+
+    fn round_trip<P: Pixel + PartialEq + fmt::Debug>() {
+        for &compression in &[TgaCompression::Uncompressed, TgaCompression::Rle] {
+            for &flipped in &[false, true] {
+                let image: Image<P> = test_image(flipped);
+                let mut writer = BufWriter::new(Vec::new());
+                image.write_tga(&mut writer, compression).unwrap();
+                let data = writer.into_inner().unwrap();
+
+                let read: Image<P> = Image::try_from_tga(&mut data.as_slice()).unwrap();
+                assert_eq!((read.width, read.height), (image.width, image.height));
+                assert_eq!(read.flipped, flipped, "{:?}", compression);
+                assert!(
+                    read.pixels == image.pixels,
+                    "{:?} flipped {}",
+                    compression,
+                    flipped
+                );
+            }
+        }
+    }
+
+    /// A TGA file with the given image type, size, bits per pixel, and image
+    /// descriptor, followed by the rest of the data as it is.
+    fn tga(
+        image_type: u8,
+        (width, height): (u16, u16),
+        bit_depth: u8,
+        descriptor: u8,
+        rest: &[u8],
+    ) -> Vec<u8> {
+        let mut data = vec![0; 18];
+        data[2] = image_type;
+        data[12..14].copy_from_slice(&width.to_le_bytes());
+        data[14..16].copy_from_slice(&height.to_le_bytes());
+        data[16] = bit_depth;
+        data[17] = descriptor;
+        data.extend_from_slice(rest);
+        data
+    }
+
+    fn read(data: &[u8]) -> Result<Image<Rgba>, TgaError> {
+        Image::try_from_tga(&mut &data[..])
+    }
+
+    /// the image descriptor for rows stored top first, without alpha bits
+    const TOP_LEFT: u8 = 0b10_0000;
+
+    #[test]
+    fn reads_true_color_of_every_depth() {
+        let bgr = tga(2, (2, 1), 24, TOP_LEFT, &[3, 2, 1, 6, 5, 4]);
+        assert_eq!(read(&bgr).unwrap().pixels, [[1, 2, 3, 255], [4, 5, 6, 255]]);
+        // alpha is only kept if the header says there are alpha bits
+        let bgra = [3, 2, 1, 128, 6, 5, 4, 0];
+        let with_alpha = tga(2, (2, 1), 32, TOP_LEFT | 8, &bgra);
+        assert_eq!(
+            read(&with_alpha).unwrap().pixels,
+            [[1, 2, 3, 128], [4, 5, 6, 0]]
+        );
+        let without = tga(2, (2, 1), 32, TOP_LEFT, &bgra);
+        assert_eq!(
+            read(&without).unwrap().pixels,
+            [[1, 2, 3, 255], [4, 5, 6, 255]]
+        );
+        // 5 bits per channel, red and then blue
+        let packed = tga(2, (2, 1), 16, TOP_LEFT, &[0x00, 0x7c, 0x1f, 0x00]);
+        assert_eq!(
+            read(&packed).unwrap().pixels,
+            [[255, 0, 0, 255], [0, 0, 255, 255]]
+        );
+        assert!(matches!(
+            read(&tga(2, (2, 1), 8, TOP_LEFT, &[0, 0])),
+            Err(TgaError::UnsupportedBitDepth(8))
+        ));
+    }
+
+    #[test]
+    fn reads_grayscale() {
+        let gray = tga(3, (2, 1), 8, TOP_LEFT, &[10, 20]);
+        assert_eq!(
+            read(&gray).unwrap().pixels,
+            [[10, 10, 10, 255], [20, 20, 20, 255]]
+        );
+        let gray_alpha = tga(3, (2, 1), 16, TOP_LEFT | 8, &[10, 100, 20, 200]);
+        let expected = [[10, 10, 10, 100], [20, 20, 20, 200]];
+        assert_eq!(read(&gray_alpha).unwrap().pixels, expected);
+        // a run of two, then one raw pixel
+        let rle = tga(11, (3, 1), 8, TOP_LEFT, &[0x81, 50, 0x00, 60]);
+        let grays: Vec<u8> = read(&rle).unwrap().pixels.iter().map(|p| p[0]).collect();
+        assert_eq!(grays, [50, 50, 60]);
+    }
+
+    #[test]
+    fn honors_every_origin() {
+        // the descriptor's origin bits, and the pixels in the order we keep them
+        let origins = [
+            (0b10_0000, [1, 2, 3, 4], false),
+            (0b00_0000, [3, 4, 1, 2], true),
+            (0b11_0000, [2, 1, 4, 3], false),
+            (0b01_0000, [4, 3, 2, 1], true),
+        ];
+        for &(descriptor, expected, flipped) in origins.iter() {
+            let image = read(&tga(3, (2, 2), 8, descriptor, &[1, 2, 3, 4])).unwrap();
+            let grays: Vec<u8> = image.pixels.iter().map(|p| p[0]).collect();
+            assert_eq!(grays, expected, "origin {:b}", descriptor >> 4);
+            assert_eq!(image.flipped, flipped);
+        }
+    }
+
+    #[test]
+    fn skips_the_image_id() {
+        let mut data = tga(3, (1, 1), 8, TOP_LEFT, b"id!\x2a");
+        data[0] = 3;
+        assert_eq!(read(&data).unwrap().pixels, [[42, 42, 42, 255]]);
+    }
+
+    #[test]
+    fn reads_rle_packets_within_rows() {
+        // a raw pixel and a run of two, then a run of three
+        let packets = [0x00, 3, 2, 1, 0x81, 6, 5, 4, 0x82, 9, 8, 7];
+        let image = read(&tga(10, (3, 2), 24, TOP_LEFT, &packets)).unwrap();
+        let reds: Vec<u8> = image.pixels.iter().map(|p| p[0]).collect();
+        assert_eq!(reds, [1, 4, 4, 7, 7, 7]);
+        let truncated = tga(10, (3, 2), 24, TOP_LEFT, &packets[..packets.len() - 1]);
+        assert!(matches!(
+            read(&truncated),
+            Err(TgaError::TruncatedPixelData)
+        ));
+        // a run of six does not fit in the first row, even if it would fill the image
+        match read(&tga(10, (3, 2), 24, TOP_LEFT, &[0x85, 3, 2, 1])) {
+            Err(TgaError::RlePacketOverrun { row: 0 }) => {}
+            other => panic!("expected an overrun, got {:?}", other.err()),
         }
     }
 
-    packet_size
+    /// Add a color map of `length` entries of `bits` each, the first one being `start`.
+    fn with_colormap(mut data: Vec<u8>, start: u16, length: u16, bits: u8) -> Vec<u8> {
+        data[1] = 1;
+        data[3..5].copy_from_slice(&start.to_le_bytes());
+        data[5..7].copy_from_slice(&length.to_le_bytes());
+        data[7] = bits;
+        data
+    }
+
+    #[test]
+    fn reads_color_mapped_images() {
+        // one row of pixels, indexing a color map of two entries starting at 2
+        let color_mapped = |image_type, bit_depth, width, map: &[u8], pixels: &[u8], bits| {
+            let data = tga(image_type, (width, 1), bit_depth, TOP_LEFT, map);
+            with_colormap([&data[..], pixels].concat(), 2, 2, bits)
+        };
+        // red and blue, as entries 2 and 3
+        let bgr_map = [0, 0, 255, 255, 0, 0];
+        let (red, blue) = ([255, 0, 0, 255], [0, 0, 255, 255]);
+        let raw = color_mapped(1, 8, 3, &bgr_map, &[2, 3, 2], 24);
+        assert_eq!(read(&raw).unwrap().pixels, [red, blue, red]);
+
+        // 16 bit indices, into 16 bit entries
+        let packed_map = [0x00, 0x7c, 0x1f, 0x00];
+        let wide = color_mapped(1, 16, 2, &packed_map, &[3, 0, 2, 0], 16);
+        assert_eq!(read(&wide).unwrap().pixels, [blue, red]);
+
+        // a run of blue, and a raw red
+        let rle = color_mapped(9, 8, 3, &bgr_map, &[0x81, 3, 0x00, 2], 24);
+        assert_eq!(read(&rle).unwrap().pixels, [blue, blue, red]);
+
+        let outside = color_mapped(1, 8, 1, &bgr_map, &[1], 24);
+        assert!(matches!(
+            read(&outside),
+            Err(TgaError::ColormapIndexOutOfRange(1))
+        ));
+        assert!(matches!(
+            read(&tga(1, (1, 1), 8, TOP_LEFT, &[0])),
+            Err(TgaError::MissingColormap)
+        ));
+    }
+
+    #[test]
+    fn skips_unused_color_maps() {
+        // three 8 bit entries, which we could not read, but need not
+        let data = tga(2, (1, 1), 24, TOP_LEFT, &[7, 8, 9, 3, 2, 1]);
+        let data = with_colormap(data, 0, 3, 8);
+        assert_eq!(read(&data).unwrap().pixels, [[1, 2, 3, 255]]);
+        let truncated = with_colormap(tga(2, (1, 1), 24, TOP_LEFT, &[7, 8]), 0, 3, 8);
+        assert!(matches!(
+            read(&truncated),
+            Err(TgaError::TruncatedPixelData)
+        ));
+        // while images using them do need them
+        let used = with_colormap(tga(1, (1, 1), 8, TOP_LEFT, &[7, 8, 9, 0]), 0, 3, 8);
+        assert!(matches!(
+            read(&used),
+            Err(TgaError::UnsupportedColormapDepth(8))
+        ));
+    }
+
+    #[test]
+    fn huge_sizes_need_their_rows() {
+        // 65535 by 65535 pixels, with data for one
+        let data = tga(2, (65535, 65535), 24, TOP_LEFT, &[3, 2, 1]);
+        assert!(matches!(read(&data), Err(TgaError::TruncatedPixelData)));
+    }
+
+    #[test]
+    fn round_trips_rgb() {
+        round_trip::<Color>();
+    }
+
+    #[test]
+    fn round_trips_rgba() {
+        round_trip::<Rgba>();
+    }
+
+    #[test]
+    fn rle_is_smaller_for_runs() {
+        let image: Image<Rgba> = test_image(false);
+        let size = |compression| {
+            let mut writer = BufWriter::new(Vec::new());
+            image.write_tga(&mut writer, compression).unwrap();
+            writer.into_inner().unwrap().len()
+        };
+        assert!(size(TgaCompression::Rle) < size(TgaCompression::Uncompressed));
+    }
+
+    #[test]
+    fn too_big_images_are_an_error() {
+        let image: Image = Image::filled(65536, 1, [0, 0, 0]);
+        let mut writer = BufWriter::new(Vec::new());
+        let error = image
+            .write_tga(&mut writer, TgaCompression::Uncompressed)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(writer.into_inner().unwrap().is_empty());
+    }
 }
@@ -0,0 +1,169 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! [Tone mapping], squeezing the colors of an HDR image
+//! into what an 8 bit image can show.
+//!
+//! [Tone mapping]: https://en.wikipedia.org/wiki/Tone_mapping
+//!
+
+use crate::image::Color;
+use crate::image::ColorSpace;
+use crate::image::HdrColor;
+use crate::image::HdrImage;
+use crate::image::Image;
+use crate::srgb;
+
+/// Curves taking HDR values to 0.0..=1.0.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToneMap {
+    /// cut off everything brighter than white
+    Clamp,
+    /// x / (1 + x), gentle, but never quite reaching white
+    Reinhard,
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve, with more contrast
+    Aces,
+}
+
+impl ToneMap {
+    fn apply(self, x: f64) -> f64 {
+        let x = x.max(0.0);
+        match self {
+            ToneMap::Clamp => x.min(1.0),
+            ToneMap::Reinhard => x / (1.0 + x),
+            ToneMap::Aces => {
+                let mapped = (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+                mapped.min(1.0)
+            }
+        }
+    }
+}
+
+/// Turns HDR images into 8 bit images.
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapper {
+    pub curve: ToneMap,
+    /// in stops, each one doubling the brightness before the curve is applied
+    pub exposure: f64,
+    /// encode the result as sRGB, like displays expect,
+    /// only turn it off for HDR colors that are not linear light
+    pub srgb: bool,
+}
+
+impl Default for ToneMapper {
+    /// The ACES curve, no exposure change, sRGB encoded.
+    fn default() -> Self {
+        ToneMapper::new(ToneMap::Aces)
+    }
+}
+
+impl ToneMapper {
+    /// A tone mapper using the curve with no exposure change, sRGB encoded.
+    pub fn new(curve: ToneMap) -> ToneMapper {
+        ToneMapper {
+            curve,
+            exposure: 0.0,
+            srgb: true,
+        }
+    }
+
+    /// Change the exposure by the given number of stops.
+    pub fn with_exposure(mut self, stops: f64) -> ToneMapper {
+        self.exposure = stops;
+        self
+    }
+
+    /// Map a single HDR color.
+    pub fn map_color(&self, hdr: HdrColor) -> Color {
+        let scale = self.exposure.exp2();
+        let mut color = [0; 3];
+        for (c, h) in color.iter_mut().zip(hdr.iter()) {
+            let mut mapped = self.curve.apply(*h as f64 * scale);
+            if self.srgb {
+                mapped = srgb::encode(mapped);
+            }
+            *c = (mapped * 255.0).round() as u8;
+        }
+        color
+    }
+
+    /// Map every pixel of the HDR image, keeping its size and orientation.
+    /// The HDR colors are taken as linear light whatever the image is tagged as,
+    /// the result is tagged by how it was encoded.
+    pub fn map(&self, hdr: &HdrImage) -> Image {
+        Image {
+            width: hdr.width,
+            height: hdr.height,
+            flipped: hdr.flipped,
+            color_space: if self.srgb {
+                ColorSpace::Srgb
+            } else {
+                ColorSpace::Linear
+            },
+            pixels: hdr.pixels.iter().map(|p| self.map_color(*p)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [ToneMap; 3] = [ToneMap::Clamp, ToneMap::Reinhard, ToneMap::Aces];
+
+    #[test]
+    fn maps_black_and_white() {
+        for &curve in CURVES.iter() {
+            assert_eq!(curve.apply(0.0), 0.0, "{:?}", curve);
+            assert_eq!(curve.apply(-1.0), 0.0, "{:?}", curve);
+        }
+        assert_eq!(ToneMap::Clamp.apply(1.0), 1.0);
+        assert_eq!(ToneMap::Reinhard.apply(1.0), 0.5);
+        // 2.54 / 3.16
+        assert!((ToneMap::Aces.apply(1.0) - 0.803_8).abs() < 1e-4);
+    }
+
+    #[test]
+    fn brighter_is_never_darker() {
+        for &curve in CURVES.iter() {
+            let mut before = 0.0;
+            for i in 1..=2000 {
+                let mapped = curve.apply(i as f64 / 100.0);
+                assert!(mapped >= before, "{:?} at {}", curve, i);
+                assert!(mapped <= 1.0, "{:?} at {}", curve, i);
+                before = mapped;
+            }
+        }
+        // but white is only reached by some
+        assert!(ToneMap::Reinhard.apply(1e6) < 1.0);
+        assert_eq!(ToneMap::Aces.apply(100.0), 1.0);
+    }
+
+    #[test]
+    fn exposes_and_encodes() {
+        let mut mapper = ToneMapper::new(ToneMap::Clamp).with_exposure(1.0);
+        mapper.srgb = false;
+        assert_eq!(mapper.map_color([0.25, 0.5, 2.0]), [128, 255, 255]);
+        let mapper = ToneMapper::new(ToneMap::Clamp);
+        assert_eq!(mapper.map_color([0.5, 0.0, 1.0]), [188, 0, 255]);
+
+        let mut hdr: HdrImage = Image::filled(2, 3, [0.5; 3]);
+        hdr.flipped = true;
+        let image = mapper.map(&hdr);
+        assert_eq!((image.width, image.height, image.flipped), (2, 3, true));
+        assert_eq!(image.color_space, ColorSpace::Srgb);
+        assert!(image.pixels.iter().all(|&p| p == [188; 3]));
+    }
+}
@@ -0,0 +1,660 @@
+// Copyright 2021 Fabian Bergström
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Just enough of [zlib] and [deflate] to read and write PNG files.
+//! Compression only uses the fixed Huffman codes, so it is
+//! not as small as it could be, but a lot smaller than nothing.
+//! Decompression handles everything deflate streams can contain.
+//!
+//! [zlib]: https://tools.ietf.org/html/rfc1950
+//! [deflate]: https://tools.ietf.org/html/rfc1951
+//!
+
+use std::error;
+use std::fmt;
+
+/// Compress the data into a zlib stream.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window, and no preset dictionary
+    let mut out = vec![0x78, 0x01];
+    let mut bits = BitWriter::new(&mut out);
+    deflate_fixed(&mut bits, data);
+    bits.flush();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Decompress a zlib stream.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, InflateError> {
+    decompress_at_most(data, usize::MAX)
+}
+
+/// Decompress a zlib stream that should have at most `limit` bytes of data,
+/// stopping with `TooLong` as soon as it has more, instead of taking up any
+/// memory a few bytes of compressed data ask for.
+pub fn decompress_at_most(data: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    if data.len() < 2 {
+        return Err(InflateError::Truncated);
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    let header_ok = cmf & 0x0f == 8 && cmf >> 4 <= 7 && u16::from_be_bytes([cmf, flg]) % 31 == 0;
+    if !header_ok {
+        return Err(InflateError::BadHeader);
+    }
+    if flg & 0b0010_0000 != 0 {
+        return Err(InflateError::PresetDictionary);
+    }
+
+    let mut bits = BitReader::new(&data[2..]);
+    let out = inflate(&mut bits, limit)?;
+
+    let checksum = bits.remaining_bytes();
+    if checksum.len() < 4 {
+        return Err(InflateError::Truncated);
+    }
+    let expected = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    if adler32(&out) != expected {
+        return Err(InflateError::ChecksumMismatch);
+    }
+    Ok(out)
+}
+
+/// Why a zlib stream could not be decompressed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InflateError {
+    /// the stream does not start with a valid zlib header for deflate data
+    BadHeader,
+    /// the stream needs a dictionary we don't have
+    PresetDictionary,
+    /// block type 3 is reserved
+    BadBlockType,
+    /// the length of a stored block does not match its complement
+    BadStoredLength,
+    /// the Huffman code lengths do not make a usable code
+    BadCodeLengths,
+    /// a symbol that has no meaning was decoded
+    BadSymbol,
+    /// a match refers to data before the start of the stream
+    BadDistance,
+    /// the stream ends before the end of the last block
+    Truncated,
+    /// the stream has more data than the limit it was decompressed with
+    TooLong,
+    /// the decompressed data does not match the Adler-32 checksum
+    ChecksumMismatch,
+}
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            InflateError::BadHeader => "bad zlib header",
+            InflateError::PresetDictionary => "preset dictionaries are not supported",
+            InflateError::BadBlockType => "bad deflate block type",
+            InflateError::BadStoredLength => "bad stored block length",
+            InflateError::BadCodeLengths => "bad Huffman code lengths",
+            InflateError::BadSymbol => "bad deflate symbol",
+            InflateError::BadDistance => "match distance too far back",
+            InflateError::Truncated => "truncated zlib stream",
+            InflateError::TooLong => "more data than expected",
+            InflateError::ChecksumMismatch => "Adler-32 checksum mismatch",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl error::Error for InflateError {}
+
+/// The [Adler-32] checksum zlib streams end with.
+///
+/// [Adler-32]: https://en.wikipedia.org/wiki/Adler-32
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    // 5552 bytes is as many as we can sum before the sums can overflow
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+/// Deflate streams are packed starting with the least significant bit of each byte.
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(out: &'a mut Vec<u8>) -> BitWriter<'a> {
+        BitWriter {
+            out,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// Write the lowest `n` bits of `value`, least significant first.
+    fn write(&mut self, value: u32, n: u32) {
+        self.buffer |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are written most significant bit first.
+    fn write_code(&mut self, code: u32, n: u32) {
+        let reversed = code.reverse_bits() >> (32 - n);
+        self.write(reversed, n);
+    }
+
+    /// Pad the last byte with zeroes.
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions to try before settling for the best match so far.
+const MAX_CHAIN: usize = 64;
+const END_OF_BLOCK: u32 = 256;
+
+/// Lengths 3 to 258 are encoded as a symbol 257 to 285 plus extra bits.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Distances 1 to 32768 are encoded as a symbol 0 to 29 plus extra bits.
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Write all data as a single block using the fixed Huffman codes.
+fn deflate_fixed(bits: &mut BitWriter, data: &[u8]) {
+    // last block, fixed Huffman codes
+    bits.write(1, 1);
+    bits.write(0b01, 2);
+
+    let mut chains = HashChains::new(data.len());
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = chains.longest_match(data, pos);
+        if length >= MIN_MATCH {
+            write_length(bits, length);
+            write_distance(bits, distance);
+            for i in pos..pos + length {
+                chains.insert(data, i);
+            }
+            pos += length;
+        } else {
+            write_literal(bits, data[pos] as u32);
+            chains.insert(data, pos);
+            pos += 1;
+        }
+    }
+    write_literal(bits, END_OF_BLOCK);
+}
+
+/// Earlier positions in the data, chained by the hash of the 3 bytes starting there,
+/// to quickly find where repeated strings might be.
+struct HashChains {
+    /// the most recent position for each hash
+    head: Vec<usize>,
+    /// the position before each position with the same hash
+    prev: Vec<usize>,
+}
+
+const HASH_SIZE: usize = 1 << 15;
+const NO_POS: usize = usize::MAX;
+
+impl HashChains {
+    fn new(len: usize) -> HashChains {
+        HashChains {
+            head: vec![NO_POS; HASH_SIZE],
+            prev: vec![NO_POS; len],
+        }
+    }
+
+    fn hash(data: &[u8], i: usize) -> usize {
+        let h = (data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize;
+        h & (HASH_SIZE - 1)
+    }
+
+    fn insert(&mut self, data: &[u8], i: usize) {
+        if i + MIN_MATCH <= data.len() {
+            let h = HashChains::hash(data, i);
+            self.prev[i] = self.head[h];
+            self.head[h] = i;
+        }
+    }
+
+    /// The length and distance of the longest earlier match for the data at `pos`.
+    fn longest_match(&self, data: &[u8], pos: usize) -> (usize, usize) {
+        if pos + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+        let max_length = MAX_MATCH.min(data.len() - pos);
+        let (mut best_length, mut best_distance) = (0, 0);
+        let mut candidate = self.head[HashChains::hash(data, pos)];
+        let mut chain = 0;
+        while candidate != NO_POS && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+            let length = data[candidate..]
+                .iter()
+                .zip(&data[pos..pos + max_length])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best_length {
+                best_length = length;
+                best_distance = pos - candidate;
+                if length == max_length {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate];
+            chain += 1;
+        }
+        (best_length, best_distance)
+    }
+}
+
+/// Write a literal/length symbol with its fixed Huffman code.
+fn write_literal(bits: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => bits.write_code(0b0011_0000 + symbol, 8),
+        144..=255 => bits.write_code(0b1_1001_0000 + symbol - 144, 9),
+        256..=279 => bits.write_code(symbol - 256, 7),
+        _ => bits.write_code(0b1100_0000 + symbol - 280, 8),
+    }
+}
+
+fn write_length(bits: &mut BitWriter, length: usize) {
+    let i = LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .expect("match length is at least 3");
+    write_literal(bits, 257 + i as u32);
+    bits.write(
+        (length - LENGTH_BASE[i] as usize) as u32,
+        LENGTH_EXTRA[i] as u32,
+    );
+}
+
+fn write_distance(bits: &mut BitWriter, distance: usize) {
+    let i = DIST_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .expect("match distance is at least 1");
+    // distance codes are all 5 bits
+    bits.write_code(i as u32, 5);
+    bits.write(
+        (distance - DIST_BASE[i] as usize) as u32,
+        DIST_EXTRA[i] as u32,
+    );
+}
+
+/// Reads deflate streams, least significant bit of each byte first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// Read `n` bits, at most 16, the first one read ending up least significant.
+    fn read(&mut self, n: u32) -> Result<u32, InflateError> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or(InflateError::Truncated)?;
+            self.buffer |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Skip to the start of the next byte.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+
+    /// The data after what has been read, call `align` first.
+    fn remaining_bytes(&self) -> &'a [u8] {
+        // reading never leaves whole bytes in the buffer
+        &self.data[self.pos..]
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length
+/// and the symbols sorted by their codes.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build the code from the code length for each symbol, 0 meaning unused.
+    fn new(lengths: &[u8]) -> Result<Huffman, InflateError> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // codes of each length can't be more than what is left of the code space
+        let mut left: i32 = 1;
+        for &count in counts.iter().skip(1) {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err(InflateError::BadCodeLengths);
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    /// Decode a symbol a bit at a time, codes are read most significant bit first.
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, InflateError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.read(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InflateError::BadSymbol)
+    }
+}
+
+/// Inflate blocks up to the last one, into at most `limit` bytes.
+fn inflate(bits: &mut BitReader, limit: usize) -> Result<Vec<u8>, InflateError> {
+    let mut out = Vec::new();
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => inflate_stored(bits, &mut out, limit)?,
+            1 => {
+                let (lit, dist) = fixed_codes();
+                inflate_block(bits, &mut out, limit, &lit, &dist)?
+            }
+            2 => {
+                let (lit, dist) = dynamic_codes(bits)?;
+                inflate_block(bits, &mut out, limit, &lit, &dist)?
+            }
+            _ => return Err(InflateError::BadBlockType),
+        }
+        if last {
+            bits.align();
+            return Ok(out);
+        }
+    }
+}
+
+fn inflate_stored(
+    bits: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+) -> Result<(), InflateError> {
+    bits.align();
+    let len = bits.read(16)?;
+    let nlen = bits.read(16)?;
+    if len != !nlen & 0xffff {
+        return Err(InflateError::BadStoredLength);
+    }
+    if len as usize > limit - out.len() {
+        return Err(InflateError::TooLong);
+    }
+    for _ in 0..len {
+        out.push(bits.read(8)? as u8);
+    }
+    Ok(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    let lit = Huffman::new(&lengths).expect("fixed literal code is valid");
+    let dist = Huffman::new(&[5; 30]).expect("fixed distance code is valid");
+    (lit, dist)
+}
+
+/// The order code length code lengths are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn dynamic_codes(bits: &mut BitReader) -> Result<(Huffman, Huffman), InflateError> {
+    let nlen = bits.read(5)? as usize + 257;
+    let ndist = bits.read(5)? as usize + 1;
+    let ncode = bits.read(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(InflateError::BadCodeLengths);
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(ncode) {
+        code_lengths[i] = bits.read(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    // the literal/length and distance code lengths are run-length encoded together
+    let mut lengths = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let (length, repeat) = match code_length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = *lengths.last().ok_or(InflateError::BadCodeLengths)?;
+                (prev, 3 + bits.read(2)?)
+            }
+            17 => (0, 3 + bits.read(3)?),
+            18 => (0, 11 + bits.read(7)?),
+            _ => return Err(InflateError::BadSymbol),
+        };
+        for _ in 0..repeat {
+            lengths.push(length);
+        }
+    }
+    if lengths.len() > nlen + ndist || lengths[END_OF_BLOCK as usize] == 0 {
+        return Err(InflateError::BadCodeLengths);
+    }
+
+    let lit = Huffman::new(&lengths[..nlen])?;
+    let dist = Huffman::new(&lengths[nlen..])?;
+    Ok((lit, dist))
+}
+
+fn inflate_block(
+    bits: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+    lit: &Huffman,
+    dist: &Huffman,
+) -> Result<(), InflateError> {
+    loop {
+        let symbol = lit.decode(bits)? as usize;
+        if symbol < END_OF_BLOCK as usize {
+            if out.len() == limit {
+                return Err(InflateError::TooLong);
+            }
+            out.push(symbol as u8);
+        } else if symbol == END_OF_BLOCK as usize {
+            return Ok(());
+        } else {
+            let i = symbol - 257;
+            if i >= LENGTH_BASE.len() {
+                return Err(InflateError::BadSymbol);
+            }
+            let length = LENGTH_BASE[i] as usize + bits.read(LENGTH_EXTRA[i] as u32)? as usize;
+
+            let i = dist.decode(bits)? as usize;
+            if i >= DIST_BASE.len() {
+                return Err(InflateError::BadSymbol);
+            }
+            let distance = DIST_BASE[i] as usize + bits.read(DIST_EXTRA[i] as u32)? as usize;
+            if distance > out.len() {
+                return Err(InflateError::BadDistance);
+            }
+            // matches are how a few bytes turn into a lot of data
+            if length > limit - out.len() {
+                return Err(InflateError::TooLong);
+            }
+            // the match may overlap what it is copying, so go a byte at a time
+            let start = out.len() - distance;
+            for i in start..start + length {
+                out.push(out[i]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// "hello" in a stored block
+    const STORED: [u8; 16] = [
+        0x78, 0x01, 0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o', 0x06, 0x2c, 0x02,
+        0x15,
+    ];
+
+    /// what zlib compresses `dynamic_data` to, in a block with its own Huffman codes
+    const DYNAMIC: [u8; 53] = [
+        0x78, 0xda, 0xed, 0xcc, 0xd1, 0x09, 0x00, 0x30, 0x08, 0x03, 0xd1, 0x59, 0x73, 0x76, 0xff,
+        0x19, 0x2a, 0xd5, 0x40, 0x87, 0x88, 0x5f, 0x0f, 0x95, 0x2b, 0xf5, 0x1c, 0x09, 0xd5, 0x08,
+        0xad, 0x1a, 0xde, 0xad, 0xb0, 0xf0, 0x0e, 0x5f, 0xf9, 0xff, 0x9e, 0xa8, 0x94, 0x53, 0x4e,
+        0x39, 0xe5, 0x2d, 0x5f, 0xcb, 0x7e, 0xae, 0xd3,
+    ];
+
+    fn dynamic_data() -> Vec<u8> {
+        (0..1130)
+            .map(|i| {
+                if i % 5 != 0 {
+                    b"aaaaaaaab"[i % 9]
+                } else {
+                    b"cd"[i % 2]
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inflates_stored_blocks() {
+        assert_eq!(decompress(&STORED).unwrap(), b"hello");
+        let mut bad_length = STORED;
+        bad_length[5] = 0;
+        assert_eq!(decompress(&bad_length), Err(InflateError::BadStoredLength));
+    }
+
+    #[test]
+    fn inflates_fixed_blocks() {
+        let data = b"the quick brown fox jumps over the lazy dog, the quick brown fox again";
+        let compressed = compress(data);
+        // BTYPE 1, the fixed codes
+        assert_eq!((compressed[2] >> 1) & 3, 1);
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed).unwrap(), &data[..]);
+        assert_eq!(decompress(&compress(b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn inflates_dynamic_blocks() {
+        // BTYPE 2, codes stored in the block
+        assert_eq!((DYNAMIC[2] >> 1) & 3, 2);
+        assert_eq!(decompress(&DYNAMIC).unwrap(), dynamic_data());
+    }
+
+    #[test]
+    fn rejects_bad_streams() {
+        assert_eq!(decompress(&STORED[..10]), Err(InflateError::Truncated));
+        assert_eq!(decompress(&DYNAMIC[..30]), Err(InflateError::Truncated));
+        let mut checksum = DYNAMIC;
+        checksum[52] ^= 1;
+        assert_eq!(decompress(&checksum), Err(InflateError::ChecksumMismatch));
+        let mut header = STORED;
+        header[1] = 0;
+        assert_eq!(decompress(&header), Err(InflateError::BadHeader));
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        assert_eq!(decompress_at_most(&STORED, 5).unwrap(), b"hello");
+        assert_eq!(decompress_at_most(&STORED, 4), Err(InflateError::TooLong));
+        let data = dynamic_data();
+        assert_eq!(decompress_at_most(&DYNAMIC, data.len()).unwrap(), data);
+        assert_eq!(
+            decompress_at_most(&DYNAMIC, data.len() - 1),
+            Err(InflateError::TooLong)
+        );
+        // a megabyte of zeros compresses to a few kilobytes, but needs the room
+        let zeros = compress(&vec![0; 1 << 20]);
+        assert!(zeros.len() < 1 << 13, "{}", zeros.len());
+        assert_eq!(decompress_at_most(&zeros, 1000), Err(InflateError::TooLong));
+    }
+}